@@ -1,40 +1,672 @@
+mod progress;
+mod station;
+
+use async_io::block_on;
 use camino::Utf8PathBuf;
-use clap::{Args, Parser};
-use picotool::{picotool_reset::reset_usb_device, PicoTool};
+use clap::{error::ErrorKind, Args, CommandFactory, Parser};
+use picotool::{
+    device::{list_devices, wait_for_device, DeviceMode, RpDevice},
+    picotool_reset::reset_to_bootsel,
+    reboot::{ActivityLed, RebootMode},
+    save::{SaveFormat, SaveRange},
+    uf2::Uf2Family,
+    verify::VerifyReport,
+    AsyncPicoTool, DeviceSelector, EraseRange, Error, FlashResult, LoadSummary, PicoTool,
+};
+use progress::ProgressBar;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+// how long a board gets to come back in BOOTSEL mode after --force-reset
+const RESET_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Parser)]
 struct Cli {
-    /// Force-reset a USB device that supports the "Reset to Bootsel" instruction
-    #[arg(short, global = true)]
+    /// Reset a board running firmware with the reset interface into BOOTSEL mode
+    /// first, and reboot it back to its application afterwards
+    #[arg(short, long, global = true)]
     force_reset: bool,
+    /// Wait for a matching board to be plugged in, if none is connected yet
+    #[arg(short, long, global = true)]
+    wait: bool,
+    #[command(flatten)]
+    selector: SelectorArgs,
     #[command(subcommand)]
     cmd: Subcommand,
 }
 
+/// Pick one device when several are connected. Given criteria must all match
 #[derive(Debug, Args)]
-struct WriteArgs {
-    /// UF2 file to flash
+struct SelectorArgs {
+    /// USB bus number of the device
+    #[arg(long, global = true)]
+    bus: Option<u8>,
+    /// USB device address of the device on its bus
+    #[arg(long, global = true)]
+    address: Option<u8>,
+    /// Physical port path of the device, as shown by `list` (e.g. 1-1.4)
+    #[arg(long, global = true)]
+    port: Option<String>,
+    /// USB serial number of the device
+    #[arg(long, global = true)]
+    serial: Option<String>,
+    /// USB vendor ID of the device, in hex
+    #[arg(long, global = true, value_parser = parse_hex_u16)]
+    vid: Option<u16>,
+    /// USB product ID of the device, in hex
+    #[arg(long, global = true, value_parser = parse_hex_u16)]
+    pid: Option<u16>,
+}
+
+impl From<SelectorArgs> for DeviceSelector {
+    fn from(args: SelectorArgs) -> Self {
+        DeviceSelector {
+            bus: args.bus,
+            address: args.address,
+            port: args.port,
+            serial: args.serial,
+            vid: args.vid,
+            pid: args.pid,
+        }
+    }
+}
+
+/// Exit as clap does when options don't go together
+fn usage_error(message: &str) -> ! {
+    Cli::command()
+        .error(ErrorKind::ArgumentConflict, message)
+        .exit()
+}
+
+fn parse_hex_u16(s: &str) -> Result<u16, std::num::ParseIntError> {
+    let digits = s.strip_prefix("0x").or(s.strip_prefix("0X")).unwrap_or(s);
+    u16::from_str_radix(digits, 16)
+}
+
+fn parse_hex_u32(s: &str) -> Result<u32, std::num::ParseIntError> {
+    let digits = s.strip_prefix("0x").or(s.strip_prefix("0X")).unwrap_or(s);
+    u32::from_str_radix(digits, 16)
+}
+
+#[derive(Debug, Args)]
+struct ImageArgs {
+    /// UF2, ELF, Intel HEX or .bin file
     target_file: Utf8PathBuf,
+    /// Use the blocks for this UF2 family even if the connected chip can't run it
+    /// (rp2040, rp2350-arm-s, rp2350-arm-ns, rp2350-riscv, absolute or data).
+    /// UF2 input only
+    #[arg(long, conflicts_with = "offset")]
+    family: Option<Uf2Family>,
+    /// Where a .bin file goes, in hex: an offset into flash, or an absolute flash
+    /// address. Defaults to the start of flash
+    #[arg(long, value_parser = parse_hex_u32)]
+    offset: Option<u32>,
+}
+
+impl ImageArgs {
+    /// Exit with a usage error if the options don't suit the file
+    fn check(&self) {
+        if self.offset.is_some() && !self.has_extension("bin") {
+            usage_error("--offset only applies to .bin files");
+        }
+        if self.family.is_some() && !self.has_extension("uf2") {
+            usage_error("--family only applies to .uf2 files");
+        }
+    }
+
+    fn has_extension(&self, name: &str) -> bool {
+        self.target_file
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(name))
+    }
+
+    /// Load the file as the options say
+    async fn load(&self, tool: &mut AsyncPicoTool) -> Result<LoadSummary, Error> {
+        let path = self.target_file.as_std_path();
+        match (self.family, self.offset) {
+            (Some(family), _) => tool.flash_uf2_family(path, family).await,
+            (None, Some(offset)) => tool.flash_bin(path, offset).await,
+            (None, None) => tool.load(path).await,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+struct WriteArgs {
+    #[command(flatten)]
+    image: ImageArgs,
+    /// Only erase and write flash sectors whose contents differ from the new image
+    #[arg(long)]
+    diff: bool,
+    /// Remember what was written to each board, and skip sectors it already holds
+    /// next time. Kept in the user cache directory unless --cache-dir is given
+    #[arg(long)]
+    cache: bool,
+    /// Directory for --cache to keep its records in
+    #[arg(long, requires = "cache")]
+    cache_dir: Option<Utf8PathBuf>,
+    /// Flash every matching board at once, rather than insisting on just one
+    #[arg(long)]
+    all: bool,
+}
+
+impl WriteArgs {
+    fn configure(&self, tool: &mut AsyncPicoTool) {
+        tool.set_differential(self.diff);
+        if self.cache {
+            let dir = self
+                .cache_dir
+                .clone()
+                .map(PathBuf::from)
+                .or_else(default_cache_dir);
+            tool.set_cache_dir(dir);
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+struct SaveArgs {
+    /// File to save to, as .bin, .uf2 or .elf going by its extension
+    filename: Utf8PathBuf,
+    /// Save the program at the start of flash, up to its end as recorded in its binary
+    /// info or else its last sector that isn't blank. The default
+    #[arg(short, long, group = "range")]
+    program: bool,
+    /// Save all of flash
+    #[arg(short, long, group = "range")]
+    all: bool,
+    /// Save all of main SRAM
+    #[arg(long, group = "range")]
+    ram: bool,
+    /// Save the bootrom
+    #[arg(long, group = "range")]
+    rom: bool,
+    /// Save memory from this address, in hex, up to --to
+    #[arg(long, value_parser = parse_hex_u32, group = "range", requires = "to")]
+    from: Option<u32>,
+    /// End of the memory to save, in hex. Not included
+    #[arg(long, value_parser = parse_hex_u32, requires = "from")]
+    to: Option<u32>,
+    /// Family to tag UF2 blocks with. Defaults to the connected chip's
+    #[arg(long)]
+    family: Option<Uf2Family>,
+}
+
+#[derive(Debug, Args)]
+struct EraseArgs {
+    /// Erase all of flash
+    #[arg(short, long, conflicts_with = "from", required_unless_present = "from")]
+    all: bool,
+    /// Erase from this address, in hex, up to --to. Must be on a sector boundary
+    #[arg(long, value_parser = parse_hex_u32, requires = "to")]
+    from: Option<u32>,
+    /// End of the flash to erase, in hex. Not included, and must be on a sector boundary
+    #[arg(long, value_parser = parse_hex_u32, requires = "from")]
+    to: Option<u32>,
+    /// Read the flash back afterwards and check it is blank
+    #[arg(long)]
+    verify: bool,
+}
+
+/// Reboot modes the BOOTSEL options can't be given with. clap doesn't enforce
+/// `requires = "bootsel"` once another arg of the "mode" group is present
+const OTHER_REBOOT_MODES: [&str; 3] = ["pc", "ram_image_start", "flash_update"];
+
+#[derive(Debug, Args)]
+struct RebootArgs {
+    /// Reboot into BOOTSEL mode rather than the application
+    #[arg(short = 'u', long, group = "mode")]
+    bootsel: bool,
+    /// Leave the USB mass storage interface out of BOOTSEL mode
+    #[arg(long, requires = "bootsel", conflicts_with_all = OTHER_REBOOT_MODES)]
+    disable_msd: bool,
+    /// Leave the PICOBOOT interface out of BOOTSEL mode
+    #[arg(long, requires = "bootsel", conflicts_with_all = OTHER_REBOOT_MODES)]
+    disable_picoboot: bool,
+    /// GPIO for BOOTSEL mode to light while it is busy
+    #[arg(long, requires = "bootsel", conflicts_with_all = OTHER_REBOOT_MODES)]
+    led: Option<u8>,
+    /// Drive the --led pin low when active. RP2350 only
+    #[arg(long, requires = "led")]
+    led_active_low: bool,
+    /// Start running at this address, in hex, with the stack pointer at --sp
+    #[arg(long, value_parser = parse_hex_u32, group = "mode", requires = "sp")]
+    pc: Option<u32>,
+    /// Stack pointer to start --pc with, in hex
+    #[arg(long, value_parser = parse_hex_u32, requires = "pc")]
+    sp: Option<u32>,
+    /// Boot the image already in SRAM at this address, in hex. RP2350 only
+    #[arg(long, value_parser = parse_hex_u32, group = "mode", requires = "ram_image_size")]
+    ram_image_start: Option<u32>,
+    /// Size of the --ram-image-start image, in hex
+    #[arg(long, value_parser = parse_hex_u32, requires = "ram_image_start")]
+    ram_image_size: Option<u32>,
+    /// Boot normally, treating the image at this flash address, in hex, as just
+    /// written so it can be committed. RP2350 only
+    #[arg(long, value_parser = parse_hex_u32, group = "mode")]
+    flash_update: Option<u32>,
+    /// Milliseconds to wait before rebooting
+    #[arg(long, default_value_t = 500)]
+    delay: u32,
+    /// Set the bootrom's no-return-on-success reboot flag. RP2350 only
+    #[arg(long)]
+    no_return: bool,
+}
+
+impl RebootArgs {
+    fn mode(&self) -> RebootMode {
+        if self.bootsel {
+            return RebootMode::Bootsel {
+                disable_msd: self.disable_msd,
+                disable_picoboot: self.disable_picoboot,
+                activity_led: self.led.map(|pin| ActivityLed {
+                    pin,
+                    active_low: self.led_active_low,
+                }),
+            };
+        }
+        match (self.pc, self.sp, self.ram_image_start, self.ram_image_size) {
+            (Some(pc), Some(sp), ..) => RebootMode::PcSp { pc, sp },
+            (_, _, Some(start), Some(size)) => RebootMode::RamImage { start, size },
+            _ => self.flash_update.map_or(RebootMode::Application, |buffer| {
+                RebootMode::FlashUpdate { buffer }
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+struct StationArgs {
+    #[command(flatten)]
+    image: ImageArgs,
+    /// Append a record of each board to this file, as JSON lines if it ends in .json
+    /// and CSV otherwise
+    #[arg(long)]
+    log: Option<Utf8PathBuf>,
 }
 
 #[derive(clap::Subcommand)]
 enum Subcommand {
     /// Load data into flash on your RP microcontroller
     Load(WriteArgs),
+    /// Check that the device holds a file's contents, without writing to flash
+    Verify(ImageArgs),
+    /// List connected RP devices in BOOTSEL mode or running firmware with the reset
+    /// interface
+    List,
+    /// Save flash, RAM or ROM contents to a file
+    Save(SaveArgs),
+    /// Erase flash
+    Erase(EraseArgs),
+    /// Reboot the device, into the application by default
+    Reboot(RebootArgs),
+    /// Flash every board plugged in in BOOTSEL mode, several at once, until stopped
+    Station(StationArgs),
 }
 
-fn main() {
-    let cli = Cli::parse();
+/// Process exit code for each class of failure, so scripts can tell them apart
+fn exit_code(err: &Error) -> u8 {
+    match err {
+        Error::Io(_) => 2,
+        Error::Uf2(_)
+        | Error::Elf(_)
+        | Error::Hex(_)
+        | Error::FamilyMismatch { .. }
+        | Error::OutOfFlash { .. }
+        | Error::OutOfMemory { .. }
+        | Error::SectorAlignment { .. } => 3,
+        Error::NotSupported { .. } | Error::InvalidReboot(_) => 9,
+        Error::NoDevice
+        | Error::MultipleDevices { .. }
+        | Error::NoInterface
+        | Error::NoResetInterface => 4,
+        Error::Usb(_) | Error::Transfer(_) | Error::ShortTransfer { .. } => 5,
+        Error::Timeout => 6,
+        Error::Status { .. } | Error::StatusMismatch { .. } => 7,
+        Error::VerifyFailed { .. } => 8,
+        _ => 1,
+    }
+}
 
+/// Where --cache keeps its records when no directory is given
+fn default_cache_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(base.join("picotool-rs"))
+}
+
+/// Connect to the selected device, with a progress bar if anyone is watching
+fn open(selector: &DeviceSelector) -> Result<PicoTool, Error> {
+    let mut tool = PicoTool::open(selector)?;
+    if let Some(target) = tool.target() {
+        println!("Found an {target:?} in BOOTSEL mode");
+    }
+    if std::io::stdout().is_terminal() {
+        tool.set_progress(Some(Box::new(ProgressBar::new())));
+    }
+    Ok(tool)
+}
+
+/// Show where the device differs from the image, 16 bytes to a line with the
+/// differing bytes marked underneath
+fn print_mismatches(report: &VerifyReport) {
+    println!(
+        "{} of {} bytes differ",
+        report.bytes_different, report.bytes_compared
+    );
+    let hex = |bytes: &[u8]| -> String { bytes.iter().map(|b| format!("{b:02x} ")).collect() };
+    for mismatch in &report.mismatches {
+        for (i, (expected, actual)) in mismatch
+            .expected
+            .chunks(16)
+            .zip(mismatch.actual.chunks(16))
+            .enumerate()
+        {
+            let marks: String = expected
+                .iter()
+                .zip(actual)
+                .map(|(e, a)| if e == a { "   " } else { "^^ " })
+                .collect();
+            println!(
+                "{:#010x}  expected {}",
+                mismatch.addr + i as u32 * 16,
+                hex(expected)
+            );
+            println!("            actual   {}", hex(actual));
+            println!("                     {}", marks.trim_end());
+        }
+    }
+    let shown: u64 = report
+        .mismatches
+        .iter()
+        .map(|m| {
+            m.expected
+                .iter()
+                .zip(&m.actual)
+                .filter(|(e, a)| e != a)
+                .count() as u64
+        })
+        .sum();
+    if shown < report.bytes_different {
+        println!("... and {} more", report.bytes_different - shown);
+    }
+}
+
+/// How to refer to a board in messages: its port path, or else its bus address
+fn device_name(device: &RpDevice) -> String {
+    device
+        .port
+        .clone()
+        .unwrap_or_else(|| format!("{}-{}", device.bus, device.address))
+}
+
+/// Load the image onto every matching board at once, with a line for each
+fn load_all(selector: &DeviceSelector, args: &WriteArgs) -> Result<(), Error> {
+    let results = PicoTool::flash_many(selector, |mut tool| async move {
+        args.configure(&mut tool);
+        args.image.load(&mut tool).await
+    })?;
+    let total = results.len();
+    let mut errors = vec![];
+    for FlashResult { device, result } in results {
+        match result {
+            Ok(summary) => println!(
+                "{}: Flash success! Wrote {} sectors, skipped {}",
+                device_name(&device),
+                summary.sectors_written,
+                summary.sectors_skipped
+            ),
+            Err(e) => {
+                println!("{}: failed: {e}", device_name(&device));
+                errors.push(e);
+            }
+        }
+    }
+    if errors.is_empty() {
+        println!("Flashed all {total} boards");
+        return Ok(());
+    }
+    println!("{} of {total} boards failed", errors.len());
+    Err(errors.remove(0))
+}
+
+/// Put a board that --force-reset took out of its application back into it
+fn back_to_application(tool: &mut PicoTool, was_reset: bool) -> Result<(), Error> {
+    if was_reset {
+        tool.reboot(RebootMode::Application, 500, false)?;
+    }
+    Ok(())
+}
+
+fn save(selector: &DeviceSelector, args: SaveArgs, was_reset: bool) -> Result<(), Error> {
+    let range = match (args.all, args.ram, args.rom, args.from, args.to) {
+        (true, ..) => SaveRange::Flash,
+        (_, true, ..) => SaveRange::Ram,
+        (_, _, true, ..) => SaveRange::Rom,
+        (_, _, _, Some(start), Some(end)) if end > start => SaveRange::Range { start, end },
+        (_, _, _, Some(_), Some(_)) => usage_error("--to must be after --from"),
+        _ => SaveRange::Program,
+    };
+    let path = args.filename.as_std_path();
+    let format = match (SaveFormat::for_path(path), args.family) {
+        (SaveFormat::Uf2(_), family) => SaveFormat::Uf2(family),
+        (_, Some(_)) => usage_error("--family only applies to .uf2 files"),
+        (format, None) => format,
+    };
+
+    let mut tool = open(selector)?;
+    let saved = tool.save(range, format, path)?;
+    println!(
+        "Saved {:#010x}-{:#010x} ({} bytes) to {}",
+        saved.start,
+        saved.end,
+        saved.len(),
+        args.filename
+    );
+    back_to_application(&mut tool, was_reset)
+}
+
+fn list() -> Result<(), Error> {
+    let devices = list_devices()?;
+    if devices.is_empty() {
+        println!("No RP devices found");
+    }
+    for device in devices {
+        let mode = match device.mode {
+            DeviceMode::Bootsel => "BOOTSEL",
+            DeviceMode::Application => "application",
+        };
+        let chip = device
+            .target_id
+            .map_or("unknown chip".to_string(), |t| format!("{t:?}"));
+        println!(
+            "Bus {:03} Device {:03} (port {}): {:04x}:{:04x} {} in {} mode, serial {}",
+            device.bus,
+            device.address,
+            device.port.as_deref().unwrap_or("unknown"),
+            device.vid,
+            device.pid,
+            chip,
+            mode,
+            device.serial.as_deref().unwrap_or("unknown"),
+        );
+    }
+    Ok(())
+}
+
+fn run(cli: Cli) -> Result<(), Error> {
+    let mut selector = DeviceSelector::from(cli.selector);
+    if let Subcommand::Station(station_args) = cli.cmd {
+        if cli.force_reset || cli.wait {
+            usage_error("station waits for boards in BOOTSEL mode itself, without -f or -w");
+        }
+        station_args.image.check();
+        return station::run(&selector, station_args.image, station_args.log.as_deref());
+    }
+    let all_boards = matches!(&cli.cmd, Subcommand::Load(args) if args.all);
+    if all_boards && cli.force_reset {
+        usage_error("-f can only reset one board, so doesn't go with --all");
+    }
+    if cli.wait {
+        let modes: &[DeviceMode] = if cli.force_reset {
+            &[DeviceMode::Bootsel, DeviceMode::Application]
+        } else {
+            &[DeviceMode::Bootsel]
+        };
+        println!("Waiting for a device...");
+        let device = wait_for_device(&selector, modes, None)?;
+        // with --all, every matching board is flashed, not just the first to arrive
+        if !all_boards {
+            selector = device.selector();
+        }
+    }
+    // only a board -f took out of its application goes back to it afterwards
+    let mut was_reset = false;
     if cli.force_reset {
-        reset_usb_device();
-        return;
+        (selector, was_reset) = reset_to_bootsel(&selector, RESET_TIMEOUT)?;
     }
 
-    let mut tool = PicoTool::new();
     match cli.cmd {
         Subcommand::Load(write_args) => {
-            tool.flash_uf2(write_args.target_file.as_std_path());
+            write_args.image.check();
+            if write_args.all {
+                return load_all(&selector, &write_args);
+            }
+
+            let mut tool = open(&selector)?.into_async();
+            write_args.configure(&mut tool);
+            let summary = block_on(write_args.image.load(&mut tool))?;
+            if write_args.diff || write_args.cache {
+                println!(
+                    "Wrote {} sectors, skipped {} that already matched",
+                    summary.sectors_written, summary.sectors_skipped
+                );
+            }
+            println!("Flash success!");
+        }
+        Subcommand::Verify(image) => {
+            image.check();
+
+            let mut tool = open(&selector)?;
+            let path = image.target_file.as_std_path();
+            let report = match (image.family, image.offset) {
+                (Some(family), _) => tool.verify_uf2_family(path, family)?,
+                (None, Some(offset)) => tool.verify_bin(path, offset)?,
+                (None, None) => tool.verify(path)?,
+            };
+            back_to_application(&mut tool, was_reset)?;
+            if let Some(first) = report.mismatches.first() {
+                print_mismatches(&report);
+                return Err(Error::VerifyFailed { addr: first.addr });
+            }
+            println!("Verify success! {} bytes match", report.bytes_compared);
+        }
+        Subcommand::List => {
+            list()?;
+            if was_reset {
+                back_to_application(&mut PicoTool::open(&selector)?, was_reset)?;
+            }
+        }
+        Subcommand::Save(save_args) => save(&selector, save_args, was_reset)?,
+        Subcommand::Erase(erase_args) => {
+            let range = match (erase_args.from, erase_args.to) {
+                (Some(start), Some(end)) => EraseRange::Range { start, end },
+                _ => EraseRange::All,
+            };
+            let mut tool = open(&selector)?;
+            let erased = tool.erase(range, erase_args.verify)?;
+            println!(
+                "Erased {:#010x}-{:#010x} ({} bytes)",
+                erased.start,
+                erased.end,
+                erased.len()
+            );
+            back_to_application(&mut tool, was_reset)?;
+        }
+        Subcommand::Reboot(reboot_args) => {
+            let mut tool = open(&selector)?;
+            tool.reboot(reboot_args.mode(), reboot_args.delay, reboot_args.no_return)?;
+        }
+        Subcommand::Station(_) => unreachable!("handled above"),
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reboot(args: &[&str]) -> Result<RebootMode, clap::Error> {
+        let cli = Cli::try_parse_from(["picotool", "reboot"].iter().chain(args))?;
+        match cli.cmd {
+            Subcommand::Reboot(reboot_args) => Ok(reboot_args.mode()),
+            _ => unreachable!("parsed a reboot command"),
+        }
+    }
+
+    #[test]
+    fn reboot_modes() {
+        assert_eq!(reboot(&[]).unwrap(), RebootMode::Application);
+        assert_eq!(
+            reboot(&["--pc", "20000101", "--sp", "20004000"]).unwrap(),
+            RebootMode::PcSp {
+                pc: 0x2000_0101,
+                sp: 0x2000_4000
+            }
+        );
+        assert_eq!(
+            reboot(&["--ram-image-start", "20000000", "--ram-image-size", "1000"]).unwrap(),
+            RebootMode::RamImage {
+                start: 0x2000_0000,
+                size: 0x1000
+            }
+        );
+        assert_eq!(
+            reboot(&["-u", "--disable-msd", "--led", "25", "--led-active-low"]).unwrap(),
+            RebootMode::Bootsel {
+                disable_msd: true,
+                disable_picoboot: false,
+                activity_led: Some(ActivityLed {
+                    pin: 25,
+                    active_low: true
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn reboot_options_that_dont_go_together() {
+        for args in [
+            // BOOTSEL options without BOOTSEL
+            &["--disable-msd"][..],
+            &["--disable-picoboot"],
+            &["--led", "25"],
+            &["--pc", "20000101", "--sp", "20004000", "--led", "25"],
+            // half of a PC/SP pair or RAM image window
+            &["--pc", "20000101"],
+            &["--sp", "20004000"],
+            &["--ram-image-start", "20000000"],
+            &["--ram-image-size", "1000"],
+            // more than one mode
+            &["-u", "--flash-update", "10000000"],
+        ] {
+            assert!(reboot(args).is_err(), "{args:?}");
         }
     }
 }
@@ -0,0 +1,87 @@
+use picotool::progress::{Phase, ProgressEvent, ProgressObserver};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+const BAR_WIDTH: usize = 30;
+// redrawing on every sector would spend more time on the terminal than the device
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Draws a progress bar for each phase of a load on stdout, which must be a terminal
+pub struct ProgressBar {
+    phase: Option<Phase>,
+    started: Instant,
+    last_draw: Option<Instant>,
+}
+
+impl ProgressBar {
+    pub fn new() -> Self {
+        ProgressBar {
+            phase: None,
+            started: Instant::now(),
+            last_draw: None,
+        }
+    }
+
+    /// Leave the current bar on its own line
+    fn end_line(&mut self) {
+        if self.last_draw.take().is_some() {
+            println!();
+        }
+    }
+
+    fn draw(&mut self, phase: Phase, done: u64, total: u64) {
+        let now = Instant::now();
+        if done < total
+            && self
+                .last_draw
+                .is_some_and(|last| now - last < REDRAW_INTERVAL)
+        {
+            return;
+        }
+        self.last_draw = Some(now);
+
+        let fraction = done as f64 / total.max(1) as f64;
+        let filled = (fraction * BAR_WIDTH as f64) as usize;
+        let elapsed = (now - self.started).as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            done as f64 / elapsed
+        } else {
+            0.0
+        };
+        let eta = if rate > 0.0 {
+            format!("{:.0}s", (total - done) as f64 / rate)
+        } else {
+            "?".to_string()
+        };
+        print!(
+            "\r{phase:<11} [{}{}] {:>3}% {:>7.1} KiB/s ETA {eta:<5}",
+            "#".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            (fraction * 100.0) as u32,
+            rate / 1024.0,
+        );
+        let _ = std::io::stdout().flush();
+    }
+}
+
+impl ProgressObserver for ProgressBar {
+    fn event(&mut self, event: ProgressEvent) {
+        match event {
+            ProgressEvent::Start { phase, total } => {
+                self.end_line();
+                self.phase = Some(phase);
+                self.started = Instant::now();
+                if phase == Phase::Reboot {
+                    println!("{phase}");
+                } else {
+                    self.draw(phase, 0, total);
+                }
+            }
+            ProgressEvent::Sector {
+                phase, done, total, ..
+            } => self.draw(phase, done, total),
+            ProgressEvent::Finished => self.end_line(),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{device_name, ImageArgs};
+use async_io::block_on;
+use camino::Utf8Path;
+use picotool::device::{DeviceMode, DeviceWatch, RpDevice};
+use picotool::{AsyncPicoTool, DeviceSelector, Error};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const CSV_HEADER: &str = "time,port,unique_id,chip,image_sha256,duration_ms,result,error";
+
+/// What happened to one board
+struct Record {
+    /// Seconds since the Unix epoch when flashing started
+    time: u64,
+    port: String,
+    unique_id: String,
+    chip: String,
+    image_sha256: String,
+    duration: Duration,
+    result: Result<(), String>,
+}
+
+enum LogFormat {
+    Csv,
+    Json,
+}
+
+struct Log {
+    file: File,
+    format: LogFormat,
+}
+
+impl Log {
+    fn open(path: &Utf8Path) -> io::Result<Self> {
+        let format = match path.extension() {
+            Some("json") => LogFormat::Json,
+            _ => LogFormat::Csv,
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if matches!(format, LogFormat::Csv) && file.metadata()?.len() == 0 {
+            writeln!(file, "{CSV_HEADER}")?;
+        }
+        Ok(Log { file, format })
+    }
+
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        let (result, error) = match &record.result {
+            Ok(()) => ("pass", ""),
+            Err(e) => ("fail", e.as_str()),
+        };
+        let duration_ms = record.duration.as_millis();
+        let line = match self.format {
+            // in CSV_HEADER order
+            LogFormat::Csv => [
+                record.time.to_string(),
+                csv_field(&record.port),
+                csv_field(&record.unique_id),
+                csv_field(&record.chip),
+                csv_field(&record.image_sha256),
+                duration_ms.to_string(),
+                result.to_string(),
+                csv_field(error),
+            ]
+            .join(","),
+            LogFormat::Json => format!(
+                "{{\"time\":{},\"port\":{},\"unique_id\":{},\"chip\":{},\"image_sha256\":{},\
+                 \"duration_ms\":{duration_ms},\"result\":\"{result}\",\"error\":{}}}",
+                record.time,
+                json_string(&record.port),
+                json_string(&record.unique_id),
+                json_string(&record.chip),
+                json_string(&record.image_sha256),
+                json_string(error),
+            ),
+        };
+        writeln!(self.file, "{line}")
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Flash `image` onto every board in BOOTSEL mode that `selector` matches as it is
+/// plugged in, each on its own thread, logging a record of each to `log`. Runs until
+/// the process is stopped, or until the OS stops reporting devices, in which case the
+/// boards being flashed are finished first.
+///
+/// Boards that pass aren't flashed again if they come back in BOOTSEL mode, so a
+/// broken image can't keep a board in a loop. Boards that fail are flashed again
+/// when replugged
+pub(crate) fn run(
+    selector: &DeviceSelector,
+    image: ImageArgs,
+    log: Option<&Utf8Path>,
+) -> Result<(), Error> {
+    let image_sha256: String = Sha256::digest(std::fs::read(&image.target_file)?)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    let log = log.map(Log::open).transpose()?.map(Mutex::new);
+    let station = Arc::new(Station {
+        image,
+        image_sha256,
+        log,
+        busy: Mutex::new(HashSet::new()),
+        passed: Mutex::new(HashSet::new()),
+    });
+
+    println!("Waiting for boards, press Ctrl-C to stop");
+    let mut flashing: Vec<JoinHandle<()>> = vec![];
+    let mut watch = DeviceWatch::new(selector, &[DeviceMode::Bootsel])?;
+    loop {
+        let device = match watch.next_timeout(None) {
+            Ok(device) => device,
+            Err(e) => {
+                for thread in flashing {
+                    // a panic has already been reported, and the rest should finish
+                    let _ = thread.join();
+                }
+                return Err(e);
+            }
+        };
+        let port = device_name(&device);
+        let unique_id = device.serial.clone().unwrap_or_default();
+        if !unique_id.is_empty() && station.passed.lock().unwrap().contains(&unique_id) {
+            println!("{port}: skipping {unique_id}, it has already passed");
+            continue;
+        }
+        if !station.busy.lock().unwrap().insert(port.clone()) {
+            continue;
+        }
+        let station = Arc::clone(&station);
+        flashing.retain(|thread| !thread.is_finished());
+        flashing.push(thread::spawn(move || {
+            station.flash(device, port, unique_id)
+        }));
+    }
+}
+
+struct Station {
+    image: ImageArgs,
+    image_sha256: String,
+    log: Option<Mutex<Log>>,
+    /// Ports with a board being flashed
+    busy: Mutex<HashSet<String>>,
+    /// Unique IDs of boards that have passed
+    passed: Mutex<HashSet<String>>,
+}
+
+impl Station {
+    fn flash(&self, device: RpDevice, port: String, unique_id: String) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_secs());
+        let start = Instant::now();
+        let result = self.load(&device);
+        let record = Record {
+            time,
+            port,
+            unique_id,
+            chip: device
+                .target_id
+                .map_or("unknown".to_string(), |t| format!("{t:?}")),
+            image_sha256: self.image_sha256.clone(),
+            duration: start.elapsed(),
+            result: result.map_err(|e| e.to_string()),
+        };
+
+        match &record.result {
+            Ok(()) => {
+                println!(
+                    "{}: PASS {} {} in {:.1}s",
+                    record.port,
+                    record.unique_id,
+                    record.chip,
+                    record.duration.as_secs_f64()
+                );
+                if !record.unique_id.is_empty() {
+                    self.passed.lock().unwrap().insert(record.unique_id.clone());
+                }
+            }
+            Err(e) => println!(
+                "{}: FAIL {} {}: {e}",
+                record.port, record.unique_id, record.chip
+            ),
+        }
+        if let Some(log) = &self.log {
+            if let Err(e) = log.lock().unwrap().append(&record) {
+                eprintln!("error: couldn't write to the log: {e}");
+            }
+        }
+        self.busy.lock().unwrap().remove(&record.port);
+    }
+
+    /// Flash, verify and reboot one board. Loads verify what they write
+    fn load(&self, device: &RpDevice) -> Result<(), Error> {
+        let mut tool = block_on(AsyncPicoTool::open(&device.selector()))?;
+        block_on(self.image.load(&mut tool)).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camino::Utf8PathBuf;
+
+    fn record(port: &str, result: Result<(), String>) -> Record {
+        Record {
+            time: 1_700_000_000,
+            port: port.to_string(),
+            unique_id: "E0C912952D54".to_string(),
+            chip: "Rp2040".to_string(),
+            image_sha256: "ab".repeat(32),
+            duration: Duration::from_millis(1234),
+            result,
+        }
+    }
+
+    /// Append `records` to a fresh log called `name`, twice over with the log reopened
+    /// in between, and return what ends up in it
+    fn log(name: &str, records: &[Record]) -> String {
+        let dir = std::env::temp_dir().join(format!("picotool-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = Utf8PathBuf::from_path_buf(dir.join(name)).unwrap();
+        for _ in 0..2 {
+            let mut log = Log::open(&path).unwrap();
+            for record in records {
+                log.append(record).unwrap();
+            }
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        text
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("1-2.3"), "1-2.3");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("two\r\nlines"), "\"two\r\nlines\"");
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string(""), "\"\"");
+        assert_eq!(json_string("a \"b\" \\c"), "\"a \\\"b\\\" \\\\c\"");
+        assert_eq!(json_string("x\ny\t\u{1}"), "\"x\\u000ay\\u0009\\u0001\"");
+        assert_eq!(json_string("héllo ✓"), "\"héllo ✓\"");
+    }
+
+    #[test]
+    fn csv_log() {
+        let sha = "ab".repeat(32);
+        let text = log(
+            "log.csv",
+            &[
+                record("1-2", Ok(())),
+                record(
+                    "1-3",
+                    Err("device reported \"Rebooting\", giving up".into()),
+                ),
+            ],
+        );
+        let pass = format!("1700000000,1-2,E0C912952D54,Rp2040,{sha},1234,pass,");
+        let fail = format!(
+            "1700000000,1-3,E0C912952D54,Rp2040,{sha},1234,fail,\
+             \"device reported \"\"Rebooting\"\", giving up\""
+        );
+        // the header only goes at the top of a new file
+        let expected = [CSV_HEADER, &pass, &fail, &pass, &fail].map(|l| l.to_string() + "\n");
+        assert_eq!(text, expected.concat());
+    }
+
+    #[test]
+    fn json_log() {
+        let sha = "ab".repeat(32);
+        let text = log(
+            "log.json",
+            &[
+                record("1-2", Ok(())),
+                record("1-3", Err("bad \"image\"".into())),
+            ],
+        );
+        let pass = format!(
+            "{{\"time\":1700000000,\"port\":\"1-2\",\"unique_id\":\"E0C912952D54\",\
+             \"chip\":\"Rp2040\",\"image_sha256\":\"{sha}\",\"duration_ms\":1234,\
+             \"result\":\"pass\",\"error\":\"\"}}"
+        );
+        let fail = format!(
+            "{{\"time\":1700000000,\"port\":\"1-3\",\"unique_id\":\"E0C912952D54\",\
+             \"chip\":\"Rp2040\",\"image_sha256\":\"{sha}\",\"duration_ms\":1234,\
+             \"result\":\"fail\",\"error\":\"bad \\\"image\\\"\"}}"
+        );
+        let expected = [&pass, &fail, &pass, &fail].map(|l| l.to_string() + "\n");
+        assert_eq!(text, expected.concat());
+        // every member named in the CSV header is there, in the same order
+        let names: Vec<_> = pass
+            .trim_matches(['{', '}'])
+            .split(',')
+            .map(|member| member.split(':').next().unwrap().trim_matches('"'))
+            .collect();
+        assert_eq!(names, CSV_HEADER.split(',').collect::<Vec<_>>());
+    }
+}
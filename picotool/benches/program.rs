@@ -0,0 +1,67 @@
+// Times loads against the emulated device with realistic USB and flash delays.
+// Run with `cargo bench -p picotool --features emulator`
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::{test_data, TempDir};
+use picotool::picoboot::emulator::{EmulatedDevice, Timing};
+use picotool::{PicoTool, TargetID};
+use std::path::Path;
+use std::time::Instant;
+
+const IMAGE_SIZE: usize = 1024 * 1024;
+
+fn main() {
+    let image = test_image();
+    let dir = TempDir::new("bench");
+    let path = dir.path("image.bin");
+    std::fs::write(&path, &image).expect("temp dir is writable");
+
+    // a blank device, so every sector is erased and programmed
+    bench("full load", &path, image.len(), |_| {}, false);
+
+    // the same image already on the device, with one sector changed
+    let mut stale = image.clone();
+    stale[IMAGE_SIZE / 2] ^= 0xff;
+    bench(
+        "differential load, one sector changed",
+        &path,
+        image.len(),
+        |device| device.load_flash(picotool::PICO_FLASH_START, &stale),
+        true,
+    );
+}
+
+fn bench(
+    name: &str,
+    path: &Path,
+    size: usize,
+    setup: impl Fn(&EmulatedDevice),
+    differential: bool,
+) {
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    setup(&device);
+    device.set_timing(Timing::typical());
+
+    let start = Instant::now();
+    let mut tool = PicoTool::with_transport(device).expect("emulated device accepts setup");
+    tool.set_differential(differential);
+    let summary = tool.flash_bin(path, 0).expect("load succeeds");
+    let elapsed = start.elapsed();
+
+    let kib_per_sec = size as f64 / 1024.0 / elapsed.as_secs_f64();
+    println!(
+        "{name}: {:.2?} ({kib_per_sec:.0} KiB/s), {} sectors written, {} skipped",
+        elapsed, summary.sectors_written, summary.sectors_skipped
+    );
+}
+
+/// Code-like data with some blank stretches, as a linker would leave between sections
+fn test_image() -> Vec<u8> {
+    let mut image = test_data(IMAGE_SIZE, 0x1234_5678);
+    for sector in image.chunks_mut(4096).skip(15).step_by(16) {
+        sector.fill(0xff);
+    }
+    image
+}
@@ -0,0 +1,93 @@
+// see https://github.com/raspberrypi/pico-sdk/tree/master/src/common/pico_binary_info
+// for the layout of the binary info the SDK embeds in images
+
+use crate::picoboot::usb::{AsyncPicobootConnection, PicobootTransport};
+use crate::{Error, PICO_FLASH_END, PICO_FLASH_START};
+
+const MARKER_START: u32 = 0x7188ebf2;
+const MARKER_END: u32 = 0xe71aa390;
+// the header sits just after the vector table, or on the RP2040 after boot2 as well
+const SEARCH_LEN: u32 = 1024;
+// a sanity limit, as a corrupt header could point anywhere
+const MAX_ENTRIES: u32 = 1024;
+
+const TYPE_ID_AND_INT: u16 = 5;
+const TAG_RASPBERRY_PI: u16 = u16::from_le_bytes(*b"RP");
+const ID_RP_BINARY_END: u32 = 0x68f465de;
+
+/// Find where the image at the start of flash ends, going by the binary end address the
+/// SDK records in its binary info. None if there is no binary info or it has no end
+pub(crate) async fn image_end<T: PicobootTransport>(
+    conn: &mut AsyncPicobootConnection<T>,
+) -> Result<Option<u32>, Error> {
+    let words = to_words(&conn.flash_read(PICO_FLASH_START, SEARCH_LEN).await?);
+    let Some(header) = words
+        .windows(5)
+        .find(|w| w[0] == MARKER_START && w[4] == MARKER_END)
+    else {
+        return Ok(None);
+    };
+    let (start, end, mapping_table) = (header[1], header[2], header[3]);
+    if end < start || (end - start) / 4 > MAX_ENTRIES {
+        return Ok(None);
+    }
+    let mappings = read_mappings(conn, mapping_table).await?;
+    let map = |addr: u32| {
+        mappings
+            .iter()
+            .find(|&&(_, dest_start, dest_end)| (dest_start..dest_end).contains(&addr))
+            .map_or(addr, |&(source, dest_start, _)| {
+                source.wrapping_add(addr - dest_start)
+            })
+    };
+
+    // anything outside flash can't be read in BOOTSEL mode, so must be corrupt
+    if !in_flash(map(start)) || !in_flash(map(start).saturating_add(end - start)) {
+        return Ok(None);
+    }
+    let entries = to_words(&conn.flash_read(map(start), end - start).await?);
+    for entry in entries {
+        if !in_flash(map(entry)) {
+            continue;
+        }
+        // type, tag, id, value
+        let record = to_words(&conn.flash_read(map(entry), 12).await?);
+        let (kind, tag) = (record[0] as u16, (record[0] >> 16) as u16);
+        if kind == TYPE_ID_AND_INT && tag == TAG_RASPBERRY_PI && record[1] == ID_RP_BINARY_END {
+            return Ok(Some(record[2]));
+        }
+    }
+    Ok(None)
+}
+
+/// The table of (source, destination start, destination end) ranges the SDK's startup
+/// code copies from flash into RAM, which binary info entries may point into
+async fn read_mappings<T: PicobootTransport>(
+    conn: &mut AsyncPicobootConnection<T>,
+    table: u32,
+) -> Result<Vec<(u32, u32, u32)>, Error> {
+    let mut mappings = vec![];
+    for i in 0..MAX_ENTRIES {
+        let row_addr = table.saturating_add(i * 12);
+        if !in_flash(row_addr) {
+            break;
+        }
+        let row = to_words(&conn.flash_read(row_addr, 12).await?);
+        if row[0] == 0 {
+            break;
+        }
+        mappings.push((row[0], row[1], row[2]));
+    }
+    Ok(mappings)
+}
+
+fn in_flash(addr: u32) -> bool {
+    (PICO_FLASH_START..PICO_FLASH_END - 12).contains(&addr)
+}
+
+fn to_words(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+        .collect()
+}
@@ -0,0 +1,83 @@
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+type SectorHash = [u8; 32];
+
+/// Hashes of the flash sectors PicoTool last wrote to one device, kept on disk as
+/// `<dir>/<serial>.sectors` with one `<address> <sha256>` line per sector
+pub(crate) struct SectorCache {
+    path: PathBuf,
+    hashes: BTreeMap<u32, SectorHash>,
+}
+
+impl SectorCache {
+    /// Load the cache for the device with `serial`. A missing or unreadable cache is empty
+    pub fn load(dir: &Path, serial: &str) -> Self {
+        // serials are hex strings, but don't let a strange one escape the directory
+        let name: String = serial
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect();
+        let path = dir.join(format!("{name}.sectors"));
+        let hashes = std::fs::read_to_string(&path)
+            .map(|text| text.lines().filter_map(parse_line).collect())
+            .unwrap_or_default();
+        SectorCache { path, hashes }
+    }
+
+    /// Whether the device is known to hold `contents` in the sector at `addr`
+    pub fn matches(&self, addr: u32, contents: &[u8]) -> bool {
+        self.hashes.get(&addr) == Some(&hash(contents))
+    }
+
+    pub fn insert(&mut self, addr: u32, contents: &[u8]) {
+        self.hashes.insert(addr, hash(contents));
+    }
+
+    pub fn clear(&mut self) {
+        self.hashes.clear();
+    }
+
+    /// Delete the file, so that a load which fails part way through can't leave
+    /// hashes behind for sectors it has already erased
+    pub fn invalidate(&self) -> io::Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let text: String = self
+            .hashes
+            .iter()
+            .map(|(addr, hash)| {
+                let hex: String = hash.iter().map(|b| format!("{b:02x}")).collect();
+                format!("{addr:08x} {hex}\n")
+            })
+            .collect();
+        std::fs::write(&self.path, text)
+    }
+}
+
+fn hash(contents: &[u8]) -> SectorHash {
+    Sha256::digest(contents).into()
+}
+
+fn parse_line(line: &str) -> Option<(u32, SectorHash)> {
+    let (addr, hex) = line.split_once(' ')?;
+    let addr = u32::from_str_radix(addr, 16).ok()?;
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut hash = [0; 32];
+    for (byte, pair) in hash.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some((addr, hash))
+}
@@ -0,0 +1,264 @@
+use crate::picoboot::usb::picoboot_device_type;
+use crate::picotool_reset::{has_reset_interface, RP_VID};
+use crate::{Error, TargetID};
+use async_io::{block_on, Timer};
+use futures_lite::{FutureExt, StreamExt};
+use nusb::{
+    hotplug::{HotplugEvent, HotplugWatch},
+    DeviceInfo,
+};
+use std::io;
+use std::time::{Duration, Instant};
+
+// PIDs the Pico SDK uses for its stdio USB device, which carries the reset interface
+const SDK_PID_RP2040: u16 = 0x000a;
+const SDK_PID_RP2350: u16 = 0x0009;
+
+/// Narrows device discovery down to one board when several are connected.
+///
+/// Every field that is set must match. The default selector matches any device.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceSelector {
+    /// USB bus number
+    pub bus: Option<u8>,
+    /// Device address on its bus
+    pub address: Option<u8>,
+    /// Physical port path, e.g. `1-1.4`. See [`RpDevice::port`]
+    pub port: Option<String>,
+    /// USB serial number string
+    pub serial: Option<String>,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+}
+
+impl DeviceSelector {
+    pub fn matches(&self, device: &DeviceInfo) -> bool {
+        self.bus.is_none_or(|bus| bus == device.bus_number())
+            && self
+                .address
+                .is_none_or(|address| address == device.device_address())
+            && self
+                .port
+                .as_ref()
+                .is_none_or(|port| port_path(device).as_ref() == Some(port))
+            && self
+                .serial
+                .as_deref()
+                .is_none_or(|serial| device.serial_number() == Some(serial))
+            && self.vid.is_none_or(|vid| vid == device.vendor_id())
+            && self.pid.is_none_or(|pid| pid == device.product_id())
+    }
+
+    /// Pick the one device in `devices` that this selector matches
+    pub(crate) fn select<'a>(
+        &self,
+        devices: impl IntoIterator<Item = &'a DeviceInfo>,
+    ) -> Result<&'a DeviceInfo, Error> {
+        let mut matching = devices.into_iter().filter(|d| self.matches(d));
+        let device = matching.next().ok_or(Error::NoDevice)?;
+        match matching.count() {
+            0 => Ok(device),
+            others => Err(Error::MultipleDevices { count: others + 1 }),
+        }
+    }
+}
+
+/// What an RP device is currently running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceMode {
+    /// The bootrom, exposing the PICOBOOT interface
+    Bootsel,
+    /// User firmware that exposes the reset interface
+    Application,
+}
+
+/// An RP device found on the bus by [`list_devices`], or reset by
+/// [`reset_usb_device`](crate::picotool_reset::reset_usb_device)
+#[derive(Debug, Clone)]
+pub struct RpDevice {
+    pub mode: DeviceMode,
+    /// Chip type, if it can be told from the USB IDs
+    pub target_id: Option<TargetID>,
+    pub vid: u16,
+    pub pid: u16,
+    pub bus: u8,
+    pub address: u8,
+    /// Physical port path, written `bus-port.port...` like Linux sysfs device names.
+    /// Not available on Windows
+    pub port: Option<String>,
+    pub serial: Option<String>,
+}
+
+/// Every RP device in BOOTSEL mode, plus every RP device running firmware with the
+/// reset interface
+pub fn list_devices() -> Result<Vec<RpDevice>, Error> {
+    let devices = nusb::list_devices()
+        .map_err(Error::Usb)?
+        .filter_map(|device| device_mode(&device, false).map(|mode| RpDevice::new(&device, mode)))
+        .collect();
+    Ok(devices)
+}
+
+/// Wait up to `timeout`, or for ever if None, for an RP device in one of `modes` that
+/// `selector` matches, and return it as soon as it is connected. A matching device
+/// that is already connected is returned straight away, the first if there are several.
+///
+/// As with [`reset_usb_device`](crate::picotool_reset::reset_usb_device), devices
+/// running firmware must have the Raspberry Pi VID unless `selector` names a VID
+pub fn wait_for_device(
+    selector: &DeviceSelector,
+    modes: &[DeviceMode],
+    timeout: Option<Duration>,
+) -> Result<RpDevice, Error> {
+    DeviceWatch::new(selector, modes)?.next_timeout(timeout)
+}
+
+/// Every RP device in one of a set of modes that a selector matches: first those
+/// already connected, then each one as it is plugged in. A device that leaves and
+/// comes back, for instance by rebooting into another mode, is yielded again.
+///
+/// Iterating blocks until the next device arrives, for ever if need be. If the OS stops
+/// reporting devices, it yields that error every time after
+pub struct DeviceWatch {
+    selector: DeviceSelector,
+    modes: Vec<DeviceMode>,
+    connected: std::vec::IntoIter<DeviceInfo>,
+    watch: HotplugWatch,
+}
+
+impl DeviceWatch {
+    /// Start watching. Devices running firmware must have the Raspberry Pi VID unless
+    /// `selector` names a VID
+    pub fn new(selector: &DeviceSelector, modes: &[DeviceMode]) -> Result<Self, Error> {
+        // watch before listing, so a device arriving in between isn't missed
+        let watch = nusb::watch_devices().map_err(Error::Usb)?;
+        let connected: Vec<DeviceInfo> = nusb::list_devices().map_err(Error::Usb)?.collect();
+        Ok(DeviceWatch {
+            selector: selector.clone(),
+            modes: modes.to_vec(),
+            connected: connected.into_iter(),
+            watch,
+        })
+    }
+
+    /// The next matching device, waiting up to `timeout` for it, or for ever if None.
+    /// Fails with [`Error::Timeout`] when that runs out, or [`Error::Usb`] if the OS
+    /// stops reporting devices
+    pub fn next_timeout(&mut self, timeout: Option<Duration>) -> Result<RpDevice, Error> {
+        while let Some(device) = self.connected.next() {
+            if let Some(device) = self.matching(&device) {
+                return Ok(device);
+            }
+        }
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let timed_out = async {
+            match deadline {
+                Some(deadline) => Timer::at(deadline).await,
+                None => std::future::pending().await,
+            };
+            Err(Error::Timeout)
+        };
+        let arrived = async {
+            while let Some(event) = self.watch.next().await {
+                if let HotplugEvent::Connected(device) = event {
+                    if let Some(device) = self.matching(&device) {
+                        return Ok(device);
+                    }
+                }
+            }
+            Err(Error::Usb(io::Error::other("USB hotplug events stopped")))
+        };
+        block_on(arrived.or(timed_out))
+    }
+
+    fn matching(&self, device: &DeviceInfo) -> Option<RpDevice> {
+        if !self.selector.matches(device) {
+            return None;
+        }
+        let mode = device_mode(device, self.selector.vid.is_some())?;
+        self.modes
+            .contains(&mode)
+            .then(|| RpDevice::new(device, mode))
+    }
+}
+
+impl Iterator for DeviceWatch {
+    type Item = Result<RpDevice, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_timeout(None))
+    }
+}
+
+/// What a device is running, if it is an RP device at all. Devices running firmware
+/// must have the Raspberry Pi VID unless `any_vid`
+fn device_mode(device: &DeviceInfo, any_vid: bool) -> Option<DeviceMode> {
+    if picoboot_device_type(device).is_some() {
+        Some(DeviceMode::Bootsel)
+    } else if (any_vid || device.vendor_id() == RP_VID) && has_reset_interface(device) {
+        Some(DeviceMode::Application)
+    } else {
+        None
+    }
+}
+
+impl RpDevice {
+    pub(crate) fn new(device: &DeviceInfo, mode: DeviceMode) -> Self {
+        let target_id = match mode {
+            DeviceMode::Bootsel => picoboot_device_type(device),
+            DeviceMode::Application => application_device_type(device),
+        };
+        RpDevice {
+            mode,
+            target_id,
+            vid: device.vendor_id(),
+            pid: device.product_id(),
+            bus: device.bus_number(),
+            address: device.device_address(),
+            port: port_path(device),
+            serial: device.serial_number().map(String::from),
+        }
+    }
+
+    /// A selector for exactly this device, at its current bus address
+    pub fn selector(&self) -> DeviceSelector {
+        DeviceSelector {
+            bus: Some(self.bus),
+            address: Some(self.address),
+            ..Default::default()
+        }
+    }
+}
+
+fn application_device_type(device: &DeviceInfo) -> Option<TargetID> {
+    match (device.vendor_id(), device.product_id()) {
+        (RP_VID, SDK_PID_RP2040) => Some(TargetID::Rp2040),
+        (RP_VID, SDK_PID_RP2350) => Some(TargetID::Rp2350),
+        _ => None,
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn port_path(device: &DeviceInfo) -> Option<String> {
+    // sysfs names devices after their bus and port chain already
+    let name = device.sysfs_path().file_name()?.to_str()?;
+    Some(name.to_string())
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) fn port_path(device: &DeviceInfo) -> Option<String> {
+    // the location ID holds the bus in the top byte, then one port number per nibble
+    let location = device.location_id();
+    let ports: Vec<String> = (0..6)
+        .map(|i| (location >> (20 - 4 * i)) & 0xf)
+        .take_while(|&port| port != 0)
+        .map(|port| port.to_string())
+        .collect();
+    Some(format!("{}-{}", location >> 24, ports.join(".")))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+pub(crate) fn port_path(_device: &DeviceInfo) -> Option<String> {
+    None
+}
@@ -0,0 +1,361 @@
+// see https://refspecs.linuxfoundation.org/elf/elf.pdf for the ELF32 layout
+
+use crate::{TargetID, PICO_FLASH_END, PICO_FLASH_START, PICO_SRAM_START};
+use std::collections::BTreeMap;
+
+pub(crate) const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_ARM: u16 = 40;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const ELF_HEADER_SIZE: usize = 52;
+const PROGRAM_HEADER_SIZE: usize = 32;
+const EV_CURRENT: u32 = 1;
+const PF_RWX: u32 = 0x7;
+const EF_ARM_EABI_VER5: u32 = 0x0500_0000;
+
+/// Ways an ELF file can be malformed or unsuitable for the connected chip
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ElfError {
+    #[error("not a 32-bit little-endian ELF file")]
+    Format,
+    #[error("ELF file is not an executable")]
+    NotExecutable,
+    #[error("ELF file is truncated")]
+    Truncated,
+    #[error("ELF file is built for machine type {0}, which an RP chip can't run")]
+    Machine(u16),
+    #[error("ELF file is built for RISC-V, which the {0:?} can't run")]
+    Architecture(TargetID),
+    #[error("segment at {addr:#010x} ({size} bytes) overlaps another segment")]
+    Overlap { addr: u32, size: u32 },
+    #[error("segment at {addr:#010x} ({size} bytes) is outside the {target:?}'s flash and RAM")]
+    Segment {
+        addr: u32,
+        size: u32,
+        target: TargetID,
+    },
+    #[error("RAM-linked image has a segment in flash at {0:#010x}")]
+    FlashInRamImage(u32),
+}
+
+/// Where an image expects to run from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageKind {
+    Flash,
+    Ram,
+}
+
+/// The loadable contents of an ELF executable
+#[derive(Debug, Clone)]
+pub struct ElfImage {
+    pub entry: u32,
+    pub machine: u16,
+    /// Data of each PT_LOAD segment, keyed by physical address. Segments with no
+    /// data in the file, such as .bss, are left out
+    pub segments: BTreeMap<u32, Vec<u8>>,
+}
+
+impl ElfImage {
+    /// Check the image fits the memory map of `target` and work out whether it is
+    /// linked to run from flash or from RAM, going by its entry point
+    pub fn check(&self, target: TargetID) -> Result<ImageKind, ElfError> {
+        match (self.machine, target) {
+            (EM_ARM, _) | (EM_RISCV, TargetID::Rp2350) => {}
+            (EM_RISCV, TargetID::Rp2040) => return Err(ElfError::Architecture(target)),
+            (machine, _) => return Err(ElfError::Machine(machine)),
+        }
+
+        let flash = PICO_FLASH_START as u64..PICO_FLASH_END as u64;
+        let sram = PICO_SRAM_START as u64..target.sram_end() as u64;
+        let within = |range: &std::ops::Range<u64>, start: u64, end: u64| {
+            range.start <= start && end <= range.end
+        };
+        let mut in_flash = None;
+        for (&addr, data) in &self.segments {
+            let (start, end) = (addr as u64, addr as u64 + data.len() as u64);
+            if within(&flash, start, end) {
+                in_flash.get_or_insert(addr);
+            } else if !within(&sram, start, end) {
+                return Err(ElfError::Segment {
+                    addr,
+                    size: data.len() as u32,
+                    target,
+                });
+            }
+        }
+
+        if !sram.contains(&(self.entry as u64)) {
+            return Ok(ImageKind::Flash);
+        }
+        match in_flash {
+            Some(addr) => Err(ElfError::FlashInRamImage(addr)),
+            None => Ok(ImageKind::Ram),
+        }
+    }
+}
+
+/// Parse an ELF executable for an RP chip
+pub fn parse_elf(bytes: &[u8]) -> Result<ElfImage, ElfError> {
+    if bytes.len() < ELF_HEADER_SIZE || &bytes[..4] != ELF_MAGIC {
+        return Err(ElfError::Format);
+    }
+    if bytes[4] != ELFCLASS32 || bytes[5] != ELFDATA2LSB {
+        return Err(ElfError::Format);
+    }
+    let half = |b: &[u8], i: usize| u16::from_le_bytes([b[i], b[i + 1]]);
+    let word = |b: &[u8], i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+
+    if half(bytes, 0x10) != ET_EXEC {
+        return Err(ElfError::NotExecutable);
+    }
+    let machine = half(bytes, 0x12);
+    let entry = word(bytes, 0x18);
+    let phoff = word(bytes, 0x1c) as usize;
+    let phentsize = half(bytes, 0x2a) as usize;
+    let phnum = half(bytes, 0x2c) as usize;
+    if phentsize < PROGRAM_HEADER_SIZE {
+        return Err(ElfError::Format);
+    }
+
+    let mut segments: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+    for i in 0..phnum {
+        let ph = phoff
+            .checked_add(i * phentsize)
+            .and_then(|start| bytes.get(start..start + PROGRAM_HEADER_SIZE))
+            .ok_or(ElfError::Truncated)?;
+        let (p_type, offset, paddr, filesz) =
+            (word(ph, 0), word(ph, 4), word(ph, 12), word(ph, 16));
+        if p_type != PT_LOAD || filesz == 0 {
+            continue;
+        }
+
+        let data = bytes
+            .get(offset as usize..offset as usize + filesz as usize)
+            .ok_or(ElfError::Truncated)?;
+        let (start, end) = (paddr as u64, paddr as u64 + filesz as u64);
+        let overlaps_before = segments
+            .range(..=paddr)
+            .next_back()
+            .is_some_and(|(&addr, d)| addr as u64 + d.len() as u64 > start);
+        let overlaps_after = segments
+            .range(paddr..)
+            .next()
+            .is_some_and(|(&addr, _)| (addr as u64) < end);
+        if overlaps_before || overlaps_after {
+            return Err(ElfError::Overlap {
+                addr: paddr,
+                size: filesz,
+            });
+        }
+        segments.insert(paddr, data.to_vec());
+    }
+
+    Ok(ElfImage {
+        entry,
+        machine,
+        segments,
+    })
+}
+
+/// Wrap `data` in an Arm executable with a single segment loading it at `addr`, which
+/// is also the entry point. There are no sections, just the one program header
+pub fn write_elf(addr: u32, data: &[u8]) -> Vec<u8> {
+    let data_offset = (ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE) as u32;
+    let mut out = Vec::with_capacity(data_offset as usize + data.len());
+    out.extend_from_slice(ELF_MAGIC);
+    out.extend_from_slice(&[ELFCLASS32, ELFDATA2LSB, EV_CURRENT as u8]);
+    out.resize(16, 0);
+    out.extend_from_slice(&ET_EXEC.to_le_bytes());
+    out.extend_from_slice(&EM_ARM.to_le_bytes());
+    for word in [
+        EV_CURRENT,
+        addr,
+        ELF_HEADER_SIZE as u32,
+        0,
+        EF_ARM_EABI_VER5,
+    ] {
+        out.extend_from_slice(&word.to_le_bytes());
+    }
+    // header size, program header size and count, then no section headers
+    for half in [ELF_HEADER_SIZE, PROGRAM_HEADER_SIZE, 1, 0, 0, 0] {
+        out.extend_from_slice(&(half as u16).to_le_bytes());
+    }
+
+    let size = data.len() as u32;
+    // an alignment of 1, as `addr` can be anything
+    for word in [PT_LOAD, data_offset, addr, addr, size, size, PF_RWX, 1] {
+        out.extend_from_slice(&word.to_le_bytes());
+    }
+    out.extend_from_slice(data);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An executable for `machine` with one program header per `(type, paddr, data)`
+    fn elf(machine: u16, entry: u32, segments: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let mut out = write_elf(entry, &[]);
+        out.truncate(ELF_HEADER_SIZE);
+        out[0x12..0x14].copy_from_slice(&machine.to_le_bytes());
+        out[0x2c..0x2e].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+        let mut offset = (ELF_HEADER_SIZE + segments.len() * PROGRAM_HEADER_SIZE) as u32;
+        for &(p_type, paddr, data) in segments {
+            let size = data.len() as u32;
+            for word in [p_type, offset, paddr, paddr, size, size, PF_RWX, 1] {
+                out.extend_from_slice(&word.to_le_bytes());
+            }
+            offset += size;
+        }
+        for &(_, _, data) in segments {
+            out.extend_from_slice(data);
+        }
+        out
+    }
+
+    #[test]
+    fn write_then_parse() {
+        let image = parse_elf(&write_elf(0x1000_0000, &[1, 2, 3])).unwrap();
+        assert_eq!(image.entry, 0x1000_0000);
+        assert_eq!(image.machine, EM_ARM);
+        assert_eq!(
+            image.segments,
+            BTreeMap::from([(0x1000_0000, vec![1, 2, 3])])
+        );
+    }
+
+    #[test]
+    fn only_loadable_data_is_kept() {
+        let bytes = elf(
+            EM_ARM,
+            0x1000_0000,
+            &[
+                (PT_LOAD, 0x2000_0000, &[4, 5]),
+                // a note, and a .bss with nothing in the file
+                (4, 0x1000_1000, &[9]),
+                (PT_LOAD, 0x2000_1000, &[]),
+                (PT_LOAD, 0x1000_0000, &[1, 2, 3]),
+            ],
+        );
+        let image = parse_elf(&bytes).unwrap();
+        assert_eq!(
+            image.segments,
+            BTreeMap::from([(0x1000_0000, vec![1, 2, 3]), (0x2000_0000, vec![4, 5])])
+        );
+    }
+
+    #[test]
+    fn format() {
+        let mut bytes = write_elf(0x1000_0000, &[1]);
+        assert!(matches!(parse_elf(&bytes[..40]), Err(ElfError::Format)));
+        bytes[4] = 2; // ELFCLASS64
+        assert!(matches!(parse_elf(&bytes), Err(ElfError::Format)));
+        bytes[0] = 0;
+        assert!(matches!(parse_elf(&bytes), Err(ElfError::Format)));
+    }
+
+    #[test]
+    fn not_executable() {
+        let mut bytes = write_elf(0x1000_0000, &[1]);
+        bytes[0x10] = 1; // ET_REL
+        assert!(matches!(parse_elf(&bytes), Err(ElfError::NotExecutable)));
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = write_elf(0x1000_0000, &[1, 2, 3, 4]);
+        assert!(matches!(
+            parse_elf(&bytes[..bytes.len() - 1]),
+            Err(ElfError::Truncated)
+        ));
+        assert!(matches!(
+            parse_elf(&bytes[..ELF_HEADER_SIZE + 8]),
+            Err(ElfError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn overlap() {
+        let bytes = elf(
+            EM_ARM,
+            0x1000_0000,
+            &[
+                (PT_LOAD, 0x1000_0000, &[1, 2, 3, 4]),
+                (PT_LOAD, 0x1000_0003, &[5]),
+            ],
+        );
+        assert!(matches!(
+            parse_elf(&bytes),
+            Err(ElfError::Overlap {
+                addr: 0x1000_0003,
+                size: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn check_kind() {
+        let flash = parse_elf(&write_elf(0x1000_0000, &[1])).unwrap();
+        assert_eq!(flash.check(TargetID::Rp2040).unwrap(), ImageKind::Flash);
+        let ram = parse_elf(&write_elf(PICO_SRAM_START, &[1])).unwrap();
+        assert_eq!(ram.check(TargetID::Rp2350).unwrap(), ImageKind::Ram);
+    }
+
+    #[test]
+    fn check_machine() {
+        let image = parse_elf(&elf(3, 0x1000_0000, &[(PT_LOAD, 0x1000_0000, &[1])])).unwrap();
+        assert!(matches!(
+            image.check(TargetID::Rp2040),
+            Err(ElfError::Machine(3))
+        ));
+
+        let riscv = elf(EM_RISCV, 0x1000_0000, &[(PT_LOAD, 0x1000_0000, &[1])]);
+        let image = parse_elf(&riscv).unwrap();
+        assert_eq!(image.check(TargetID::Rp2350).unwrap(), ImageKind::Flash);
+        assert!(matches!(
+            image.check(TargetID::Rp2040),
+            Err(ElfError::Architecture(TargetID::Rp2040))
+        ));
+    }
+
+    #[test]
+    fn check_segment_addresses() {
+        // past the end of the RP2040's SRAM, though within the RP2350's
+        let addr = TargetID::Rp2040.sram_end();
+        let image = parse_elf(&write_elf(addr, &[1])).unwrap();
+        assert!(matches!(
+            image.check(TargetID::Rp2040),
+            Err(ElfError::Segment { addr: a, size: 1, .. }) if a == addr
+        ));
+        assert_eq!(image.check(TargetID::Rp2350).unwrap(), ImageKind::Ram);
+
+        // running off the end of the flash window
+        let image = parse_elf(&write_elf(PICO_FLASH_END - 1, &[1, 2])).unwrap();
+        assert!(matches!(
+            image.check(TargetID::Rp2350),
+            Err(ElfError::Segment { .. })
+        ));
+    }
+
+    #[test]
+    fn check_flash_in_ram_image() {
+        let bytes = elf(
+            EM_ARM,
+            PICO_SRAM_START,
+            &[
+                (PT_LOAD, PICO_SRAM_START, &[1]),
+                (PT_LOAD, 0x1000_0000, &[2]),
+            ],
+        );
+        let image = parse_elf(&bytes).unwrap();
+        assert!(matches!(
+            image.check(TargetID::Rp2040),
+            Err(ElfError::FlashInRamImage(0x1000_0000))
+        ));
+    }
+}
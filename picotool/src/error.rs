@@ -0,0 +1,106 @@
+use crate::elf::ElfError;
+use crate::ihex::HexError;
+use crate::picoboot::cmd::PicobootStatus;
+use crate::uf2::{family_names, Uf2Error, Uf2Family};
+use crate::TargetID;
+use nusb::transfer::TransferError;
+use std::io;
+
+/// Everything that can go wrong while talking to an RP device
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Enumerating, opening or claiming the USB device failed
+    #[error("USB error: {0}")]
+    Usb(io::Error),
+    /// A bulk or control transfer failed on the bus
+    #[error("USB transfer failed: {0}")]
+    Transfer(#[from] TransferError),
+    /// The device did not complete a transfer in time
+    #[error("timed out waiting for the device")]
+    Timeout,
+    /// The device moved fewer bytes than were requested
+    #[error("short transfer: expected {expected} bytes, got {actual}")]
+    ShortTransfer { expected: usize, actual: usize },
+    /// The firmware file is not a valid UF2
+    #[error("failed to decode UF2: {0}")]
+    Uf2(#[from] Uf2Error),
+    /// The ELF file is malformed or doesn't fit the connected chip
+    #[error("unusable ELF file: {0}")]
+    Elf(#[from] ElfError),
+    /// The Intel HEX file is malformed
+    #[error("failed to decode Intel HEX: {0}")]
+    Hex(#[from] HexError),
+    /// The UF2 has no blocks for the requested family, or for any family the
+    /// connected chip can run
+    #[error(
+        "UF2 has no blocks for {} (found families: {})",
+        requested.map_or(format!("{target:?}"), |f| f.to_string()),
+        family_names(.found)
+    )]
+    FamilyMismatch {
+        target: TargetID,
+        requested: Option<Uf2Family>,
+        found: Vec<Option<u32>>,
+    },
+    /// No RP device in BOOTSEL mode is connected
+    #[error("no RP device in BOOTSEL mode found")]
+    NoDevice,
+    /// More than one connected device matched the device selector
+    #[error("found {count} matching devices, select one by bus/address, port, serial or VID/PID")]
+    MultipleDevices { count: usize },
+    /// The device does not expose a usable PICOBOOT interface
+    #[error("device does not have a PICOBOOT interface")]
+    NoInterface,
+    /// An RP device was found, but it does not expose the reset interface
+    #[error("device does not have the USB reset interface")]
+    NoResetInterface,
+    /// The device rejected a PICOBOOT command
+    #[error("device reported {status} for command {cmd_id:#04x} (token {token})")]
+    Status {
+        status: PicobootStatus,
+        cmd_id: u8,
+        token: u32,
+    },
+    /// The device's command status describes a different command than the one sent
+    #[error(
+        "command status mismatch: sent command {cmd_id:#04x} (token {token}), \
+         device reported command {status_cmd_id:#04x} (token {status_token})"
+    )]
+    StatusMismatch {
+        token: u32,
+        cmd_id: u8,
+        status_token: u32,
+        status_cmd_id: u8,
+    },
+    /// Data to be written runs past the end of flash
+    #[error("{size} bytes at {addr:#010x} don't fit in flash")]
+    OutOfFlash { addr: u32, size: usize },
+    /// Data to be written is in neither flash nor SRAM
+    #[error("{size} bytes at {addr:#010x} are in neither flash nor SRAM")]
+    OutOfMemory { addr: u32, size: usize },
+    /// An erase range doesn't line up with flash sectors
+    #[error(
+        "flash can only be erased in whole 4096 byte sectors, so {start:#010x}-{end:#010x} \
+         must start and end on a multiple of 0x1000"
+    )]
+    SectorAlignment { start: u32, end: u32 },
+    /// The flash size was needed but couldn't be worked out
+    #[error("the device didn't say how much flash is fitted")]
+    UnknownFlashSize,
+    /// The connected chip can't do what was asked of it
+    #[error("{feature} is not supported on the {target:?}")]
+    NotSupported {
+        target: TargetID,
+        feature: &'static str,
+    },
+    /// A REBOOT2 command's options don't go together
+    #[error("invalid reboot: {0}")]
+    InvalidReboot(&'static str),
+    /// Flash contents did not match what was written
+    #[error("verification failed at {addr:#010x}")]
+    VerifyFailed { addr: u32 },
+    /// Reading or writing a local file failed
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
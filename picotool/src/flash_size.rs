@@ -0,0 +1,116 @@
+// see the rp2040 datasheet section 4.10 for the SSI, and the rp2350 datasheet
+// section 5.4.8 for get_sys_info and the FLASH_DEVINFO layout
+
+// the RP2040 bootrom can't say how big its flash is, so this little Thumb program
+// is loaded into SRAM and run to ask the flash for its JEDEC ID. EXIT_XIP has
+// already left the SSI in 8 bit serial mode:
+//
+//     push {r4, r5, r6, lr}
+//     ldr  r0, =0x4001800c    ; GPIO_QSPI_SS_CTRL
+//     ldr  r1, =0x18000000    ; SSI
+//     ldr  r4, [r0]           ; to put back after
+//     movs r2, #3
+//     lsls r2, r2, #8         ; OUTOVER
+//     mov  r5, r4
+//     bics r5, r2
+//     movs r3, #2
+//     lsls r3, r3, #8         ; OUTOVER low, selecting the flash
+//     orrs r5, r3
+//     str  r5, [r0]
+//     movs r6, #0
+//     movs r3, #0x9f          ; RDID
+//     movs r5, #4
+// loop:
+//     str  r3, [r1, #0x60]    ; DR0
+// wait:
+//     ldr  r2, [r1, #0x28]    ; SR
+//     lsrs r2, r2, #4         ; RFNE into carry
+//     bcc  wait
+//     ldr  r2, [r1, #0x60]
+//     lsls r6, r6, #8
+//     orrs r6, r2
+//     movs r3, #0
+//     subs r5, #1
+//     bne  loop
+//     str  r4, [r0]
+//     adr  r3, result
+//     str  r6, [r3]
+//     pop  {r4, r5, r6, pc}
+const JEDEC_ID_CODE: [u16; 30] = [
+    0xb570, 0x480e, 0x490e, 0x6804, 0x2203, 0x0212, 0x4625, 0x4395, 0x2302, 0x021b, 0x431d, 0x6005,
+    0x2600, 0x239f, 0x2504, 0x660b, 0x6a8a, 0x0912, 0xd3fc, 0x6e0a, 0x0236, 0x4316, 0x2300, 0x3d01,
+    0xd1f5, 0x6004, 0xa303, 0x601e, 0xbd70, 0xbf00,
+];
+const GPIO_QSPI_SS_CTRL: u32 = 0x4001800c;
+const SSI_BASE: u32 = 0x18000000;
+/// Where the program leaves the ID: the byte clocked in during the command, then the
+/// manufacturer, memory type and capacity bytes
+pub(crate) const JEDEC_ID_RESULT_OFFSET: u32 = 0x44;
+
+/// The program above followed by its literal pool and a word for the result
+pub(crate) fn jedec_id_program() -> Vec<u8> {
+    let code = JEDEC_ID_CODE.iter().flat_map(|h| h.to_le_bytes());
+    let literals = [GPIO_QSPI_SS_CTRL, SSI_BASE, 0];
+    code.chain(literals.iter().flat_map(|w| w.to_le_bytes()))
+        .collect()
+}
+
+/// The flash size a JEDEC ID gives, as two to the power of its capacity byte. None if
+/// nothing answered or the capacity is outside what fits in the XIP window
+pub(crate) fn size_from_jedec_id(id: u32) -> Option<u32> {
+    let manufacturer = (id >> 16) as u8;
+    let capacity = id as u8;
+    if manufacturer == 0 || manufacturer == 0xff || !(16..=24).contains(&capacity) {
+        return None;
+    }
+    Some(1 << capacity)
+}
+
+pub(crate) const SYS_INFO_FLASH_DEV_INFO: u32 = 0x8;
+const FLASH_DEVINFO_CS0_SIZE_SHIFT: u32 = 8;
+const FLASH_DEVINFO_CS0_SIZE_MASK: u32 = 0xf;
+
+/// The size of the flash on chip select 0 from a GET_INFO response asking for
+/// `SYS_INFO_FLASH_DEV_INFO`: a word count, the flags included, then FLASH_DEVINFO.
+/// None if the bootrom left it out or says no flash is fitted
+pub(crate) fn size_from_sys_info(words: &[u32]) -> Option<u32> {
+    let (&included, &devinfo) = (words.get(1)?, words.get(2)?);
+    if included & SYS_INFO_FLASH_DEV_INFO == 0 {
+        return None;
+    }
+    // 0 is none, then 8KiB doubling up to 16MiB at 12
+    match (devinfo >> FLASH_DEVINFO_CS0_SIZE_SHIFT) & FLASH_DEVINFO_CS0_SIZE_MASK {
+        size @ 1..=12 => Some(4096 << size),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jedec_id() {
+        // W25Q16JV and W25Q128JV
+        assert_eq!(size_from_jedec_id(0x00ef4015), Some(2 << 20));
+        assert_eq!(size_from_jedec_id(0x00ef4018), Some(16 << 20));
+        // nothing driving MISO, or too big for the window
+        assert_eq!(size_from_jedec_id(0x00ffffff), None);
+        assert_eq!(size_from_jedec_id(0), None);
+        assert_eq!(size_from_jedec_id(0x00ef4019), None);
+    }
+
+    #[test]
+    fn sys_info() {
+        let info = |devinfo| [2, SYS_INFO_FLASH_DEV_INFO, devinfo];
+        assert_eq!(size_from_sys_info(&info(0x0a00)), Some(4 << 20));
+        assert_eq!(size_from_sys_info(&info(0x0c00)), Some(16 << 20));
+        assert_eq!(size_from_sys_info(&info(0)), None);
+        assert_eq!(size_from_sys_info(&[1, 0]), None);
+    }
+
+    #[test]
+    fn program_leaves_room_for_the_result() {
+        assert_eq!(jedec_id_program().len() as u32, JEDEC_ID_RESULT_OFFSET + 4);
+    }
+}
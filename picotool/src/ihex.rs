@@ -0,0 +1,281 @@
+// see https://developer.arm.com/documentation/ka003292 for the Intel HEX record format
+
+use std::collections::BTreeMap;
+
+const RECORD_DATA: u8 = 0x00;
+const RECORD_EOF: u8 = 0x01;
+const RECORD_EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const RECORD_START_SEGMENT_ADDRESS: u8 = 0x03;
+const RECORD_EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const RECORD_START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// Ways an Intel HEX file can be malformed. Lines are numbered from 1
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum HexError {
+    #[error("line {0} is not a valid record")]
+    Syntax(usize),
+    #[error("line {0} has a bad checksum")]
+    Checksum(usize),
+    #[error("line {line} has unknown record type {kind:#04x}")]
+    RecordType { line: usize, kind: u8 },
+    #[error("line {0} writes to {1:#010x}, which another record also writes")]
+    Overlap(usize, u32),
+    #[error("file has no end of file record")]
+    NoEndOfFile,
+}
+
+/// Parse an Intel HEX file into contiguous address ranges.
+///
+/// Both extended segment and extended linear addressing are understood. Start address
+/// records are accepted but ignored, and anything after the end of file record is too.
+pub fn parse_ihex(bytes: &[u8]) -> Result<BTreeMap<u32, Vec<u8>>, HexError> {
+    let mut records: Vec<(usize, u32, Vec<u8>)> = vec![];
+    let mut base: u32 = 0;
+    let mut eof = false;
+    for (i, line) in bytes.split(|&b| b == b'\n').enumerate() {
+        let line_no = i + 1;
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            continue;
+        }
+
+        let record = decode_record(line).ok_or(HexError::Syntax(line_no))?;
+        if record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(HexError::Checksum(line_no));
+        }
+        let (len, kind) = (record[0] as usize, record[3]);
+        let offset = u16::from_be_bytes([record[1], record[2]]) as u32;
+        let data = &record[4..4 + len];
+        let value = || match data {
+            [hi, lo] => Ok(u16::from_be_bytes([*hi, *lo]) as u32),
+            _ => Err(HexError::Syntax(line_no)),
+        };
+        match kind {
+            RECORD_DATA if data.is_empty() => {}
+            RECORD_DATA => records.push((line_no, base.wrapping_add(offset), data.to_vec())),
+            RECORD_EOF => {
+                eof = true;
+                break;
+            }
+            RECORD_EXTENDED_SEGMENT_ADDRESS => base = value()? << 4,
+            RECORD_EXTENDED_LINEAR_ADDRESS => base = value()? << 16,
+            RECORD_START_SEGMENT_ADDRESS | RECORD_START_LINEAR_ADDRESS => {}
+            kind => {
+                return Err(HexError::RecordType {
+                    line: line_no,
+                    kind,
+                })
+            }
+        }
+    }
+    if !eof {
+        return Err(HexError::NoEndOfFile);
+    }
+
+    // records needn't be in address order, so sort them before joining up neighbours
+    records.sort_by_key(|&(_, addr, _)| addr);
+    let mut ranges: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+    let mut last: Option<(u32, u64)> = None; // (start, end) of the range being built
+    for (line_no, addr, data) in records {
+        let end = addr as u64 + data.len() as u64;
+        match last {
+            Some((_, last_end)) if (addr as u64) < last_end => {
+                return Err(HexError::Overlap(line_no, addr));
+            }
+            Some((start, last_end)) if addr as u64 == last_end => {
+                let range = ranges.get_mut(&start).expect("range was inserted");
+                range.extend_from_slice(&data);
+                last = Some((start, end));
+            }
+            _ => {
+                ranges.insert(addr, data);
+                last = Some((addr, end));
+            }
+        }
+    }
+    Ok(ranges)
+}
+
+/// Encode `data` as an Intel HEX file that writes it at `addr`, in 16 byte records
+/// with extended linear address records wherever the upper 16 bits change
+pub fn write_ihex(addr: u32, data: &[u8]) -> Vec<u8> {
+    let mut text = String::new();
+    let mut upper = None;
+    for (i, chunk) in data.chunks(16).enumerate() {
+        let at = addr.wrapping_add((i * 16) as u32);
+        if upper != Some(at >> 16) {
+            upper = Some(at >> 16);
+            let hi = ((at >> 16) as u16).to_be_bytes();
+            text += &encode_record(RECORD_EXTENDED_LINEAR_ADDRESS, 0, &hi);
+        }
+        text += &encode_record(RECORD_DATA, at as u16, chunk);
+    }
+    text += &encode_record(RECORD_EOF, 0, &[]);
+    text.into_bytes()
+}
+
+/// One `:LLAAAATT<data>CC` line, with the checksum filled in
+fn encode_record(kind: u8, offset: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8, (offset >> 8) as u8, offset as u8, kind];
+    bytes.extend_from_slice(data);
+    bytes.push(
+        bytes
+            .iter()
+            .fold(0u8, |sum, b| sum.wrapping_add(*b))
+            .wrapping_neg(),
+    );
+    let hex: String = bytes.iter().map(|b| format!("{b:02X}")).collect();
+    format!(":{hex}\n")
+}
+
+/// Decode `:LLAAAATT<data>CC` into its bytes, checking the length matches LL
+fn decode_record(line: &[u8]) -> Option<Vec<u8>> {
+    let hex = line.strip_prefix(b":")?;
+    if hex.len() % 2 != 0 || hex.len() < 10 {
+        return None;
+    }
+    let record = hex
+        .chunks_exact(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    // length, address, type, data and checksum
+    (record.len() == 5 + record[0] as usize).then_some(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eof() -> String {
+        encode_record(RECORD_EOF, 0, &[])
+    }
+
+    fn parse(lines: &[String]) -> Result<BTreeMap<u32, Vec<u8>>, HexError> {
+        parse_ihex(lines.concat().as_bytes())
+    }
+
+    #[test]
+    fn ranges_are_merged_and_sorted() {
+        let ranges = parse(&[
+            encode_record(RECORD_EXTENDED_LINEAR_ADDRESS, 0, &[0x10, 0x00]),
+            encode_record(RECORD_DATA, 0x0004, &[5, 6, 7, 8]),
+            encode_record(RECORD_DATA, 0x0000, &[1, 2, 3, 4]),
+            encode_record(RECORD_DATA, 0x0100, &[9]),
+            encode_record(RECORD_START_LINEAR_ADDRESS, 0, &[0x10, 0, 1, 0x01]),
+            eof(),
+        ])
+        .unwrap();
+        assert_eq!(
+            ranges,
+            BTreeMap::from([
+                (0x1000_0000, vec![1, 2, 3, 4, 5, 6, 7, 8]),
+                (0x1000_0100, vec![9]),
+            ])
+        );
+    }
+
+    #[test]
+    fn segment_addressing() {
+        let ranges = parse(&[
+            encode_record(RECORD_EXTENDED_SEGMENT_ADDRESS, 0, &[0x12, 0x34]),
+            encode_record(RECORD_DATA, 0x0010, &[0xaa]),
+            eof(),
+        ])
+        .unwrap();
+        assert_eq!(ranges, BTreeMap::from([(0x12350, vec![0xaa])]));
+    }
+
+    #[test]
+    fn anything_after_end_of_file_is_ignored() {
+        let ranges = parse(&[
+            encode_record(RECORD_DATA, 0, &[1]),
+            eof(),
+            "not a record\n".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(ranges, BTreeMap::from([(0, vec![1])]));
+    }
+
+    #[test]
+    fn syntax() {
+        // line 2 says it holds 2 bytes but has 1
+        let short = ":0200000001FD\n".to_string();
+        assert!(matches!(
+            parse(&[encode_record(RECORD_DATA, 0, &[1]), short, eof()]),
+            Err(HexError::Syntax(2))
+        ));
+        assert!(matches!(
+            parse(&["0100000001FE\n".to_string(), eof()]),
+            Err(HexError::Syntax(1))
+        ));
+        assert!(matches!(
+            parse(&[":01000000ZZFE\n".to_string(), eof()]),
+            Err(HexError::Syntax(1))
+        ));
+        // an address record needs exactly two bytes
+        assert!(matches!(
+            parse(&[
+                encode_record(RECORD_EXTENDED_LINEAR_ADDRESS, 0, &[0x10]),
+                eof()
+            ]),
+            Err(HexError::Syntax(1))
+        ));
+    }
+
+    #[test]
+    fn checksum() {
+        assert!(matches!(
+            parse(&[":0100000001FF\n".to_string(), eof()]),
+            Err(HexError::Checksum(1))
+        ));
+    }
+
+    #[test]
+    fn record_type() {
+        assert!(matches!(
+            parse(&[encode_record(0x06, 0, &[]), eof()]),
+            Err(HexError::RecordType {
+                line: 1,
+                kind: 0x06
+            })
+        ));
+    }
+
+    #[test]
+    fn overlap() {
+        assert!(matches!(
+            parse(&[
+                encode_record(RECORD_DATA, 0x10, &[1, 2, 3, 4]),
+                encode_record(RECORD_DATA, 0x12, &[5]),
+                eof(),
+            ]),
+            Err(HexError::Overlap(2, 0x12))
+        ));
+    }
+
+    #[test]
+    fn no_end_of_file() {
+        assert!(matches!(
+            parse(&[encode_record(RECORD_DATA, 0, &[1])]),
+            Err(HexError::NoEndOfFile)
+        ));
+    }
+
+    #[test]
+    fn written_files_parse_back() {
+        // crosses a 64KiB boundary, so needs a second extended address record
+        let data: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let bytes = write_ihex(0x1000_ff80, &data);
+        let text = std::str::from_utf8(&bytes).unwrap();
+        let upper: Vec<_> = text
+            .lines()
+            .filter(|l| l.starts_with(":02000004"))
+            .collect();
+        assert_eq!(upper, [":020000041000EA", ":020000041001E9"]);
+        assert_eq!(
+            parse_ihex(&bytes).unwrap(),
+            BTreeMap::from([(0x1000_ff80, data)])
+        );
+    }
+}
@@ -1,104 +1,1291 @@
+mod binary_info;
+mod cache;
+pub mod device;
+pub mod elf;
+mod error;
+mod flash_size;
+pub mod ihex;
 pub mod picoboot;
 pub mod picotool_reset;
+pub mod progress;
+pub mod reboot;
+pub mod save;
+pub mod uf2;
+pub mod verify;
+
+pub use device::DeviceSelector;
+pub use error::Error;
+pub use picoboot::cmd::PicobootStatus;
 
 pub const PICO_PAGE_SIZE: usize = 256;
 pub const PICO_SECTOR_SIZE: u32 = 4096;
 pub const PICO_FLASH_START: u32 = 0x10000000;
+/// End of the 16MiB XIP window that flash is mapped into
+pub const PICO_FLASH_END: u32 = 0x11000000;
+pub const PICO_SRAM_START: u32 = 0x20000000;
 pub const PICO_STACK_POINTER: u32 = 0x20042000;
 
-use picoboot::usb::PicobootConnection;
+// GPIOs the RP2040's bootrom can light an activity LED on
+const RP2040_GPIO_COUNT: u8 = 30;
+// how many times, and how often, to try opening a device that has just come up
+const OPEN_ATTEMPTS: u32 = 10;
+const OPEN_RETRY_INTERVAL: Duration = Duration::from_millis(200);
+// reads and writes are split up so that no single command takes long enough on
+// the device to run into the USB timeout
+const MAX_TRANSFER_SIZE: usize = 64 * 1024;
+// the bootrom erases aligned blocks of this size with a single block erase
+const FLASH_BLOCK_SIZE: u32 = 64 * 1024;
+
+use device::{DeviceMode, RpDevice};
+use picoboot::cmd::{Reboot2, BOOTSEL_DISABLE_MSD, BOOTSEL_DISABLE_PICOBOOT};
+use picoboot::usb::{picoboot_devices, AsyncPicobootConnection, NusbTransport, PicobootTransport};
 
-use std::path::Path;
-use uf2_decode::convert_from_uf2;
+use async_io::{block_on, Timer};
+use cache::SectorCache;
+use elf::{parse_elf, write_elf, ImageKind, ELF_MAGIC};
+use ihex::parse_ihex;
+use progress::{Phase, ProgressEvent, ProgressObserver};
+use reboot::{reset_usb_boot_program, RebootMode};
+use save::{SaveFormat, SaveRange};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::future::Future;
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::task::Poll;
+use std::time::Duration;
+use uf2::{parse_uf2, select_image, write_uf2, Uf2Family};
+use verify::VerifyReport;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TargetID {
     Rp2040,
     Rp2350,
 }
 
-pub fn uf2_pages(bytes: Vec<u8>) -> Result<Vec<Vec<u8>>, uf2_decode::Error> {
-    let fw = convert_from_uf2(&bytes)?.0;
-    let mut fw_pages: Vec<Vec<u8>> = vec![];
-    let len = fw.len();
-    for i in (0..len).step_by(PICO_PAGE_SIZE) {
-        let size = std::cmp::min(len - i, PICO_PAGE_SIZE);
-        let mut page = fw[i..i + size].to_vec();
-        page.resize(PICO_PAGE_SIZE, 0);
-        fw_pages.push(page);
+impl TargetID {
+    /// One past the last byte of main SRAM
+    pub const fn sram_end(self) -> u32 {
+        match self {
+            TargetID::Rp2040 => 0x20042000,
+            TargetID::Rp2350 => 0x20082000,
+        }
+    }
+
+    /// One past the last byte of the bootrom, which starts at address 0
+    pub const fn rom_end(self) -> u32 {
+        match self {
+            TargetID::Rp2040 => 0x4000,
+            TargetID::Rp2350 => 0x8000,
+        }
     }
-    Ok(fw_pages)
 }
 
-pub struct PicoTool {
-    conn: PicobootConnection,
+/// Which part of flash to erase
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EraseRange {
+    /// All of flash, its size as the device reports it
+    All,
+    /// The sectors from `start` up to but not including `end`, which must both be
+    /// multiples of the 4096 byte sector size
+    Range { start: u32, end: u32 },
+}
+
+/// What a load did to the device's flash
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadSummary {
+    /// Sectors erased and programmed
+    pub sectors_written: usize,
+    /// Sectors left alone because they already held the new contents
+    pub sectors_skipped: usize,
 }
 
-impl Default for PicoTool {
-    fn default() -> Self {
-        Self::new()
+/// How a load went on one of the devices [`AsyncPicoTool::flash_many`] flashed
+#[derive(Debug)]
+pub struct FlashResult {
+    pub device: RpDevice,
+    pub result: Result<LoadSummary, Error>,
+}
+
+/// Flashes, verifies, reads and reboots one device in BOOTSEL mode. Its futures run
+/// under any async runtime
+pub struct AsyncPicoTool<T: PicobootTransport = NusbTransport> {
+    conn: AsyncPicobootConnection<T>,
+    differential: bool,
+    cache_dir: Option<PathBuf>,
+    progress: Option<Box<dyn ProgressObserver + Send>>,
+    // asked for once, as the RP2040 has to run code to find out
+    flash_size: Option<u32>,
+}
+
+impl AsyncPicoTool<NusbTransport> {
+    pub async fn new() -> Result<Self, Error> {
+        Self::open(&DeviceSelector::default()).await
+    }
+
+    /// Connect to the BOOTSEL device that `selector` matches. One that has only just
+    /// come up, say after [`reset_to_bootsel`](crate::picotool_reset::reset_to_bootsel)
+    /// or [`wait_for_device`](crate::device::wait_for_device), may not answer straight
+    /// away, so this keeps trying for a couple of seconds
+    pub async fn open(selector: &DeviceSelector) -> Result<Self, Error> {
+        Self::open_retrying(|| NusbTransport::open_selected(selector)).await
+    }
+
+    async fn open_retrying(open: impl Fn() -> Result<NusbTransport, Error>) -> Result<Self, Error> {
+        let mut attempt = 1;
+        loop {
+            let result = match open() {
+                Ok(transport) => Self::with_transport(transport).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                // there's no point waiting for a device that isn't there
+                Err(Error::NoDevice | Error::MultipleDevices { .. }) => return result,
+                Err(_) if attempt < OPEN_ATTEMPTS => {
+                    attempt += 1;
+                    Timer::after(OPEN_RETRY_INTERVAL).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Connect to every BOOTSEL device that `selector` matches and run `load` on them all
+    /// at once, each with its own connection, by polling their futures together. Returns
+    /// each device with how its load went, in the order they were found, once all are
+    /// done:
+    ///
+    /// ```no_run
+    /// # use picotool::{AsyncPicoTool, DeviceSelector};
+    /// # let path = std::path::Path::new("blink.uf2");
+    /// # async_io::block_on(async {
+    /// AsyncPicoTool::flash_many(&DeviceSelector::default(), |mut tool| async move {
+    ///     tool.load(path).await
+    /// })
+    /// .await?;
+    /// # Ok::<(), picotool::Error>(())
+    /// # });
+    /// ```
+    pub async fn flash_many<F, Fut>(
+        selector: &DeviceSelector,
+        load: F,
+    ) -> Result<Vec<FlashResult>, Error>
+    where
+        F: Fn(Self) -> Fut,
+        Fut: Future<Output = Result<LoadSummary, Error>>,
+    {
+        let devices: Vec<_> = picoboot_devices()?
+            .into_iter()
+            .filter(|d| selector.matches(d))
+            .collect();
+        if devices.is_empty() {
+            return Err(Error::NoDevice);
+        }
+
+        let load = &load;
+        let loads = devices.iter().map(|device| async move {
+            let tool = Self::open_retrying(|| NusbTransport::open_device(device)).await?;
+            load(tool).await
+        });
+        let results = join_all(loads.collect()).await;
+        Ok(devices
+            .iter()
+            .zip(results)
+            .map(|(device, result)| FlashResult {
+                device: RpDevice::new(device, DeviceMode::Bootsel),
+                result,
+            })
+            .collect())
     }
 }
 
-impl PicoTool {
-    pub fn new() -> Self {
-        let mut conn = PicobootConnection::new().unwrap();
-        conn.reset_interface();
-        conn.access_exclusive_eject()
-            .expect("failed to claim access");
-        conn.exit_xip().expect("failed to exit from xip mode");
-        PicoTool { conn }
+impl<T: PicobootTransport> AsyncPicoTool<T> {
+    pub async fn with_transport(transport: T) -> Result<Self, Error> {
+        let mut conn = AsyncPicobootConnection::with_transport(transport);
+        conn.reset_interface().await?;
+        conn.access_exclusive_eject().await?;
+        conn.exit_xip().await?;
+        Ok(AsyncPicoTool {
+            conn,
+            differential: false,
+            cache_dir: None,
+            progress: None,
+            flash_size: None,
+        })
     }
 
-    pub fn flash_uf2(&mut self, uf2: &Path) {
-        let fw = std::fs::read(uf2).unwrap();
-        let fw_pages = uf2_pages(fw).unwrap();
+    /// A [`PicoTool`] that blocks on each operation of this one
+    pub fn into_blocking(self) -> PicoTool<T> {
+        PicoTool { inner: self }
+    }
 
-        let mut erased_sectors = vec![];
+    /// Read back each flash sector before programming it, and skip sectors that
+    /// already hold the new contents. Off by default
+    pub fn set_differential(&mut self, differential: bool) {
+        self.differential = differential;
+    }
 
-        for (i, page) in fw_pages.iter().enumerate() {
-            let addr = (i * PICO_PAGE_SIZE) as u32 + PICO_FLASH_START;
-            let size = PICO_PAGE_SIZE as u32;
+    /// Keep hashes of the flash sectors written to each device in `dir`, keyed by its
+    /// serial number, and skip writing sectors the device is known to hold already.
+    ///
+    /// A page of each sector the cache would skip is read back before each load. If any
+    /// no longer match, the cache is thrown away and the load falls back to a
+    /// differential one. Erasing with this tool throws the cache away too.
+    /// Off by default, and has no effect on devices without a serial number
+    pub fn set_cache_dir(&mut self, dir: Option<PathBuf>) {
+        self.cache_dir = dir;
+    }
 
-            // Erase is by sector. Addresses must be on sector boundary
-            let sector_addr = addr - (addr % PICO_SECTOR_SIZE);
-            if !erased_sectors.contains(&sector_addr) {
-                // Sector containing this page hasn't been erased yet, erase it now
-                self.conn
-                    .flash_erase(addr, PICO_SECTOR_SIZE)
-                    .expect("failed to erase flash");
-                erased_sectors.push(sector_addr);
+    /// Report the progress of each load to `observer`. None by default
+    pub fn set_progress(&mut self, observer: Option<Box<dyn ProgressObserver + Send>>) {
+        self.progress = observer;
+    }
+
+    /// The chip this is connected to
+    pub fn target(&self) -> Option<TargetID> {
+        self.conn.get_device_type()
+    }
+
+    /// Load a UF2, ELF, Intel HEX or raw binary file. Files ending in `.bin` are written
+    /// to the start of flash, and ELF and HEX files are otherwise told by their contents
+    pub async fn load(&mut self, path: &Path) -> Result<LoadSummary, Error> {
+        match FileKind::of(path)? {
+            FileKind::Elf => self.flash_elf(path).await,
+            FileKind::Hex => self.flash_hex(path).await,
+            FileKind::Bin => self.flash_bin(path, 0).await,
+            FileKind::Uf2 => self.flash_uf2(path).await,
+        }
+    }
+
+    /// Write the data records of an Intel HEX file to their addresses
+    pub async fn flash_hex(&mut self, hex: &Path) -> Result<LoadSummary, Error> {
+        let target = self.conn.get_device_type().ok_or(Error::NoDevice)?;
+        let summary = self.program(&hex_ranges(hex)?).await?;
+        self.reboot_to_flash(target).await?;
+        Ok(summary)
+    }
+
+    /// Write a raw binary file into flash `offset` bytes from its start. Offsets
+    /// inside the flash address window are taken as absolute addresses instead
+    pub async fn flash_bin(&mut self, bin: &Path, offset: u32) -> Result<LoadSummary, Error> {
+        let target = self.conn.get_device_type().ok_or(Error::NoDevice)?;
+        let flash_end = self.flash_end().await?;
+        let summary = self.program(&bin_ranges(bin, offset, flash_end)?).await?;
+        self.reboot_to_flash(target).await?;
+        Ok(summary)
+    }
+
+    /// Load the PT_LOAD segments of an ELF file at their physical addresses, then run it
+    /// from flash or RAM depending on where it was linked
+    pub async fn flash_elf(&mut self, elf: &Path) -> Result<LoadSummary, Error> {
+        let target = self.conn.get_device_type().ok_or(Error::NoDevice)?;
+        let image = parse_elf(&std::fs::read(elf)?)?;
+        let kind = image.check(target)?;
+        let summary = self.program(&image.segments).await?;
+        if kind == ImageKind::Flash {
+            self.reboot_to_flash(target).await?;
+            return Ok(summary);
+        }
+
+        self.notify(ProgressEvent::Start {
+            phase: Phase::Reboot,
+            total: 0,
+        });
+        match target {
+            TargetID::Rp2040 => self.conn.reboot(image.entry, target.sram_end(), 500).await,
+            TargetID::Rp2350 => {
+                let start = *image.segments.keys().next().unwrap_or(&PICO_SRAM_START);
+                let end = image
+                    .segments
+                    .iter()
+                    .map(|(addr, data)| addr + data.len() as u32)
+                    .max()
+                    .unwrap_or(start);
+                // the bootrom searches a window of whole 4KiB pages for the image
+                let start = start / PICO_SECTOR_SIZE * PICO_SECTOR_SIZE;
+                let end = end.div_ceil(PICO_SECTOR_SIZE) * PICO_SECTOR_SIZE;
+                self.conn.reboot2_ram_image(start, end - start, 500).await
             }
+        }?;
+        self.notify(ProgressEvent::Finished);
+        Ok(summary)
+    }
 
-            self.conn
-                .flash_write(addr, page.to_vec())
-                .expect("failed to write flash");
+    /// Flash the blocks of a UF2 meant for the connected chip, refusing the file if it
+    /// has none
+    pub async fn flash_uf2(&mut self, uf2: &Path) -> Result<LoadSummary, Error> {
+        self.load_uf2(uf2, None).await
+    }
 
-            let read = self
-                .conn
-                .flash_read(addr, size)
-                .expect("failed to read flash");
+    /// Flash only the blocks of a UF2 tagged with `family`, whether or not the connected
+    /// chip is expected to run it
+    pub async fn flash_uf2_family(
+        &mut self,
+        uf2: &Path,
+        family: Uf2Family,
+    ) -> Result<LoadSummary, Error> {
+        self.load_uf2(uf2, Some(family)).await
+    }
 
-            let matching = page.iter().zip(&read).filter(|&(a, b)| a == b).count();
-            if matching != PICO_PAGE_SIZE {
-                panic!(
-                    "page failed to match (expected {}, got {})",
-                    PICO_PAGE_SIZE, matching
-                )
+    async fn load_uf2(
+        &mut self,
+        uf2: &Path,
+        family: Option<Uf2Family>,
+    ) -> Result<LoadSummary, Error> {
+        let target = self.conn.get_device_type().ok_or(Error::NoDevice)?;
+        let summary = self.program(&uf2_ranges(uf2, family, target)?).await?;
+        self.reboot_to_flash(target).await?;
+        Ok(summary)
+    }
+
+    /// Compare the device's memory with a UF2, ELF, Intel HEX or raw binary file, without
+    /// writing to flash. Files are recognised and placed as [`AsyncPicoTool::load`] would
+    pub async fn verify(&mut self, path: &Path) -> Result<VerifyReport, Error> {
+        let target = self.conn.get_device_type().ok_or(Error::NoDevice)?;
+        let ranges = match FileKind::of(path)? {
+            FileKind::Elf => {
+                let image = parse_elf(&std::fs::read(path)?)?;
+                image.check(target)?;
+                image.segments
+            }
+            FileKind::Hex => hex_ranges(path)?,
+            FileKind::Bin => bin_ranges(path, 0, self.flash_end().await?)?,
+            FileKind::Uf2 => uf2_ranges(path, None, target)?,
+        };
+        self.compare(&ranges).await
+    }
+
+    /// Compare flash with a raw binary file placed as [`AsyncPicoTool::flash_bin`] would
+    pub async fn verify_bin(&mut self, bin: &Path, offset: u32) -> Result<VerifyReport, Error> {
+        let flash_end = self.flash_end().await?;
+        self.compare(&bin_ranges(bin, offset, flash_end)?).await
+    }
+
+    /// Compare the device's memory with the blocks of a UF2 tagged with `family`
+    pub async fn verify_uf2_family(
+        &mut self,
+        uf2: &Path,
+        family: Uf2Family,
+    ) -> Result<VerifyReport, Error> {
+        let target = self.conn.get_device_type().ok_or(Error::NoDevice)?;
+        self.compare(&uf2_ranges(uf2, Some(family), target)?).await
+    }
+
+    async fn compare(&mut self, ranges: &BTreeMap<u32, Vec<u8>>) -> Result<VerifyReport, Error> {
+        let total = self.start_phase(
+            Phase::Verify,
+            ranges.values().map(|data| data.len() as u64).sum(),
+        );
+        let mut done = 0;
+        let mut report = VerifyReport::default();
+        for (&start, data) in ranges {
+            for (i, chunk) in data.chunks(MAX_TRANSFER_SIZE).enumerate() {
+                let chunk_addr = start + (i * MAX_TRANSFER_SIZE) as u32;
+                let read = self.conn.flash_read(chunk_addr, chunk.len() as u32).await?;
+                report.add(chunk_addr, chunk, &read);
+                self.sectors_done(Phase::Verify, chunk_addr, chunk.len(), &mut done, total);
             }
         }
+        self.notify(ProgressEvent::Finished);
+        Ok(report)
+    }
 
-        match self.conn.get_device_type().expect("No known RP chip found") {
+    /// Read part of the device's memory and write it to `path` in `format`, returning
+    /// the addresses that were saved
+    pub async fn save(
+        &mut self,
+        range: SaveRange,
+        format: SaveFormat,
+        path: &Path,
+    ) -> Result<Range<u32>, Error> {
+        let target = self.conn.get_device_type().ok_or(Error::NoDevice)?;
+        let (mut start, mut data) = self.read_memory(range).await?;
+        if let SaveFormat::Uf2(_) = format {
+            // each UF2 block holds a whole page, so take in the rest of the first and last
+            let page = PICO_PAGE_SIZE as u32;
+            let end = start + data.len() as u32;
+            let (page_start, page_end) = (start - start % page, end.next_multiple_of(page));
+            if page_start < start {
+                let mut head = self.conn.flash_read(page_start, start - page_start).await?;
+                head.append(&mut data);
+                (start, data) = (page_start, head);
+            }
+            if end < page_end {
+                data.extend(self.conn.flash_read(end, page_end - end).await?);
+            }
+        }
+        let saved = start..start + data.len() as u32;
+        let bytes = match format {
+            SaveFormat::Bin => data,
+            SaveFormat::Uf2(family) => {
+                let family = family.unwrap_or(Uf2Family::for_target(target)[0]);
+                write_uf2(start, &data, family)
+            }
+            SaveFormat::Elf => write_elf(start, &data),
+        };
+        std::fs::write(path, bytes)?;
+        Ok(saved)
+    }
+
+    /// Read part of the device's memory, returning the address it starts at and its
+    /// contents
+    pub async fn read_memory(&mut self, range: SaveRange) -> Result<(u32, Vec<u8>), Error> {
+        let target = self.conn.get_device_type().ok_or(Error::NoDevice)?;
+        let (start, end) = match range {
+            SaveRange::Program => {
+                let end = binary_info::image_end(&mut self.conn).await?;
+                match end.filter(|end| (PICO_FLASH_START + 1..=PICO_FLASH_END).contains(end)) {
+                    Some(end) => (PICO_FLASH_START, end),
+                    None => {
+                        // read it all, and cut it down after
+                        let end = self.flash_end().await?;
+                        let (start, mut data) = self.read_range(PICO_FLASH_START, end).await?;
+                        let sector_size = PICO_SECTOR_SIZE as usize;
+                        let used = data
+                            .chunks(sector_size)
+                            .rposition(|sector| sector.iter().any(|&b| b != 0xff))
+                            .map_or(0, |last| (last + 1) * sector_size);
+                        data.truncate(used);
+                        return Ok((start, data));
+                    }
+                }
+            }
+            SaveRange::Flash => (PICO_FLASH_START, self.flash_end().await?),
+            SaveRange::Ram => (PICO_SRAM_START, target.sram_end()),
+            SaveRange::Rom => (0, target.rom_end()),
+            SaveRange::Range { start, end } => (start, end.max(start)),
+        };
+        self.read_range(start, end).await
+    }
+
+    async fn read_range(&mut self, start: u32, end: u32) -> Result<(u32, Vec<u8>), Error> {
+        let total = self.start_phase(Phase::Read, (end - start) as u64);
+        let mut done = 0;
+        let mut data = Vec::with_capacity((end - start) as usize);
+        let mut addr = start;
+        while addr < end {
+            let len = (end - addr).min(MAX_TRANSFER_SIZE as u32);
+            data.extend(self.conn.flash_read(addr, len).await?);
+            self.sectors_done(Phase::Read, addr, len as usize, &mut done, total);
+            addr += len;
+        }
+        self.notify(ProgressEvent::Finished);
+        Ok((start, data))
+    }
+
+    /// Erase flash, whole sectors at a time, and optionally check it reads back blank.
+    /// Returns the addresses that were erased
+    pub async fn erase(
+        &mut self,
+        range: EraseRange,
+        blank_check: bool,
+    ) -> Result<Range<u32>, Error> {
+        let (start, end) = match range {
+            EraseRange::All => (PICO_FLASH_START, self.flash_end().await?),
+            EraseRange::Range { start, end } => (start, end),
+        };
+        if start < PICO_FLASH_START || end > PICO_FLASH_END || end < start {
+            return Err(Error::OutOfFlash {
+                addr: start,
+                size: end.saturating_sub(start) as usize,
+            });
+        }
+        if start % PICO_SECTOR_SIZE != 0 || end % PICO_SECTOR_SIZE != 0 {
+            return Err(Error::SectorAlignment { start, end });
+        }
+
+        // the cache would otherwise let the next load skip sectors that are now blank
+        if let Some(cache) = self.sector_cache() {
+            cache.invalidate()?;
+        }
+        let total = self.start_phase(Phase::Erase, (end - start) as u64);
+        let mut done = 0;
+        self.erase_sectors(start, end, &mut done, total).await?;
+        if blank_check {
+            let total = self.start_phase(Phase::Verify, (end - start) as u64);
+            let mut done = 0;
+            let mut addr = start;
+            while addr < end {
+                let len = (end - addr).min(MAX_TRANSFER_SIZE as u32);
+                let read = self.conn.flash_read(addr, len).await?;
+                if let Some(offset) = read.iter().position(|&b| b != 0xff) {
+                    return Err(Error::VerifyFailed {
+                        addr: addr + offset as u32,
+                    });
+                }
+                self.sectors_done(Phase::Verify, addr, len as usize, &mut done, total);
+                addr += len;
+            }
+        }
+        self.notify(ProgressEvent::Finished);
+        Ok(start..end)
+    }
+
+    /// Erase the sectors from `start` to `end`, reporting progress in the erase phase
+    async fn erase_sectors(
+        &mut self,
+        start: u32,
+        end: u32,
+        done: &mut u64,
+        total: u64,
+    ) -> Result<(), Error> {
+        let mut addr = start;
+        while addr < end {
+            // one command per block, so there is progress to report. The bootrom
+            // uses a block erase for each whole aligned block
+            let next = ((addr / FLASH_BLOCK_SIZE + 1) * FLASH_BLOCK_SIZE).min(end);
+            self.conn.flash_erase(addr, next - addr).await?;
+            self.sectors_done(Phase::Erase, addr, (next - addr) as usize, done, total);
+            addr = next;
+        }
+        Ok(())
+    }
+
+    /// One past the end of the flash fitted
+    async fn flash_end(&mut self) -> Result<u32, Error> {
+        Ok(PICO_FLASH_START + self.flash_size().await?)
+    }
+
+    /// How much flash is fitted, as the RP2350 bootrom reports it, or from the JEDEC ID
+    /// the RP2040's flash gives a program run in SRAM
+    async fn flash_size(&mut self) -> Result<u32, Error> {
+        if let Some(size) = self.flash_size {
+            return Ok(size);
+        }
+        let target = self.conn.get_device_type().ok_or(Error::NoDevice)?;
+        let size = match target {
             TargetID::Rp2040 => {
                 self.conn
-                    .reboot(0x0, PICO_STACK_POINTER, 500)
-                    .expect("failed to reboot device"); // sp is SRAM_END_RP2040
+                    .flash_write(PICO_SRAM_START, flash_size::jedec_id_program())
+                    .await?;
+                self.conn.exec(PICO_SRAM_START | 1).await?;
+                let result = PICO_SRAM_START + flash_size::JEDEC_ID_RESULT_OFFSET;
+                let id = self.conn.flash_read(result, 4).await?;
+                flash_size::size_from_jedec_id(u32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+            }
+            TargetID::Rp2350 => {
+                let info = self
+                    .conn
+                    .get_sys_info(flash_size::SYS_INFO_FLASH_DEV_INFO)
+                    .await?;
+                flash_size::size_from_sys_info(&info)
+            }
+        };
+        let size = size.ok_or(Error::UnknownFlashSize)?;
+        self.flash_size = Some(size);
+        Ok(size)
+    }
+
+    /// Reboot the device into `mode` after `delay_ms`. `no_return` sets the RP2350
+    /// bootrom's no-return-on-success flag, which the RP2040 has no equivalent of
+    pub async fn reboot(
+        &mut self,
+        mode: RebootMode,
+        delay_ms: u32,
+        no_return: bool,
+    ) -> Result<(), Error> {
+        let target = self.conn.get_device_type().ok_or(Error::NoDevice)?;
+        let unsupported = |feature| Err(Error::NotSupported { target, feature });
+        if target == TargetID::Rp2350 {
+            let mut reboot = match mode {
+                RebootMode::Application => Reboot2::normal(),
+                RebootMode::Bootsel {
+                    disable_msd,
+                    disable_picoboot,
+                    activity_led,
+                } => {
+                    let mut reboot = Reboot2::bootsel();
+                    if disable_msd {
+                        reboot = reboot.disable_msd();
+                    }
+                    if disable_picoboot {
+                        reboot = reboot.disable_picoboot();
+                    }
+                    if let Some(led) = activity_led {
+                        reboot = reboot.activity_led(led.pin, led.active_low);
+                    }
+                    reboot
+                }
+                RebootMode::PcSp { pc, sp } => Reboot2::pc_sp(pc, sp),
+                RebootMode::RamImage { start, size } => Reboot2::ram_image(start, size),
+                RebootMode::FlashUpdate { buffer } => Reboot2::flash_update(buffer),
+            };
+            if no_return {
+                reboot = reboot.no_return();
+            }
+            return self.reboot2(reboot.delay(delay_ms)).await;
+        }
+
+        match mode {
+            RebootMode::RamImage { .. } => return unsupported("rebooting to a RAM image"),
+            RebootMode::FlashUpdate { .. } => return unsupported("a flash update reboot"),
+            RebootMode::Bootsel {
+                activity_led: Some(led),
+                ..
+            } if led.active_low => return unsupported("an active low activity LED"),
+            RebootMode::Bootsel {
+                activity_led: Some(led),
+                ..
+            } if led.pin >= RP2040_GPIO_COUNT => {
+                return Err(Error::InvalidReboot(
+                    "the activity LED must be on one of GPIOs 0-29",
+                ))
+            }
+            _ if no_return => return unsupported("a no-return reboot"),
+            _ => {}
+        }
+        self.notify(ProgressEvent::Start {
+            phase: Phase::Reboot,
+            total: 0,
+        });
+        self.reboot_rp2040(mode, delay_ms).await?;
+        self.notify(ProgressEvent::Finished);
+        Ok(())
+    }
+
+    /// Send an RP2350 a REBOOT2 command, for the options [`AsyncPicoTool::reboot`] doesn't
+    /// cover, such as switching architecture
+    pub async fn reboot2(&mut self, reboot: Reboot2) -> Result<(), Error> {
+        let target = self.conn.get_device_type().ok_or(Error::NoDevice)?;
+        if target != TargetID::Rp2350 {
+            return Err(Error::NotSupported {
+                target,
+                feature: "REBOOT2",
+            });
+        }
+        reboot.validate()?;
+        self.notify(ProgressEvent::Start {
+            phase: Phase::Reboot,
+            total: 0,
+        });
+        self.conn.reboot2(&reboot).await?;
+        self.notify(ProgressEvent::Finished);
+        Ok(())
+    }
+
+    async fn reboot_rp2040(&mut self, mode: RebootMode, delay_ms: u32) -> Result<(), Error> {
+        match mode {
+            RebootMode::Application => self.conn.reboot(0x0, PICO_STACK_POINTER, delay_ms).await,
+            RebootMode::PcSp { pc, sp } => self.conn.reboot(pc, sp, delay_ms).await,
+            RebootMode::Bootsel {
+                disable_msd,
+                disable_picoboot,
+                activity_led,
+            } => {
+                // the REBOOT command can't reach BOOTSEL, so run code that asks the
+                // bootrom for it. That happens at once, so the delay is waited out here
+                Timer::after(Duration::from_millis(delay_ms as u64)).await;
+                let gpio_mask = activity_led.map_or(0, |led| 1 << led.pin);
+                let disable_mask = bootsel_disable_mask(disable_msd, disable_picoboot);
+                let program = reset_usb_boot_program(gpio_mask, disable_mask);
+                self.conn.flash_write(PICO_SRAM_START, program).await?;
+                match self.conn.exec(PICO_SRAM_START | 1).await {
+                    // the device drops off the bus rather than acknowledging
+                    Err(
+                        Error::Transfer(_)
+                        | Error::Timeout
+                        | Error::Usb(_)
+                        | Error::ShortTransfer { .. },
+                    ) => Ok(()),
+                    result => result,
+                }
+            }
+            // turned away by reboot()
+            RebootMode::RamImage { .. } | RebootMode::FlashUpdate { .. } => unreachable!(),
+        }
+    }
+
+    async fn reboot_to_flash(&mut self, target: TargetID) -> Result<(), Error> {
+        self.notify(ProgressEvent::Start {
+            phase: Phase::Reboot,
+            total: 0,
+        });
+        match target {
+            // sp is SRAM_END_RP2040
+            TargetID::Rp2040 => self.conn.reboot(0x0, PICO_STACK_POINTER, 500).await,
+            TargetID::Rp2350 => self.conn.reboot2_normal(500).await,
+        }?;
+        self.notify(ProgressEvent::Finished);
+        Ok(())
+    }
+
+    fn notify(&mut self, event: ProgressEvent) {
+        if let Some(observer) = &mut self.progress {
+            observer.event(event);
+        }
+    }
+
+    /// Start `phase` if it has anything to do, returning its total
+    fn start_phase(&mut self, phase: Phase, total: u64) -> u64 {
+        if total > 0 {
+            self.notify(ProgressEvent::Start { phase, total });
+        }
+        total
+    }
+
+    /// Report each sector in the `len` bytes at `addr` as done, adding them to `done`
+    fn sectors_done(&mut self, phase: Phase, addr: u32, len: usize, done: &mut u64, total: u64) {
+        let sector_size = PICO_SECTOR_SIZE as usize;
+        for offset in (0..len).step_by(sector_size) {
+            *done += sector_size.min(len - offset) as u64;
+            self.notify(ProgressEvent::Sector {
+                phase,
+                addr: addr + offset as u32,
+                done: *done,
+                total,
+            });
+        }
+    }
+
+    /// Write each address range to the device, leaving everything else untouched.
+    ///
+    /// Flash is erased a sector at a time, so sectors the ranges only partly cover are
+    /// read first and their other contents written back.
+    async fn program(&mut self, ranges: &BTreeMap<u32, Vec<u8>>) -> Result<LoadSummary, Error> {
+        self.check_ranges(ranges).await?;
+        let sector_size = PICO_SECTOR_SIZE as usize;
+        // sector address -> (new contents, which bytes the ranges set)
+        let mut sectors: BTreeMap<u32, (Vec<u8>, Vec<bool>)> = BTreeMap::new();
+        let mut ram = vec![];
+        for (&start, data) in ranges {
+            if !(PICO_FLASH_START..PICO_FLASH_END).contains(&start) {
+                // RAM needs no erase
+                ram.push((start, data));
+                continue;
+            }
+            for (i, &byte) in data.iter().enumerate() {
+                let addr = start + i as u32;
+                let sector_addr = addr - (addr % PICO_SECTOR_SIZE);
+                let (contents, set) = sectors
+                    .entry(sector_addr)
+                    .or_insert_with(|| (vec![0xff; sector_size], vec![false; sector_size]));
+                let offset = (addr - sector_addr) as usize;
+                contents[offset] = byte;
+                set[offset] = true;
+            }
+        }
+
+        let mut differential = self.differential;
+        let mut cache = self.sector_cache();
+        if let Some(cache) = &mut cache {
+            if !self.spot_check(cache, &sectors).await? {
+                // the device was changed behind our back, so compare everything instead
+                cache.clear();
+                differential = true;
+            }
+            cache.invalidate()?;
+        }
+
+        // work out which sectors need writing, joining neighbours into runs so each
+        // run can be erased, programmed and read back with as few commands as possible
+        let mut summary = LoadSummary::default();
+        let mut runs: Vec<(u32, Vec<u8>)> = vec![];
+        for (sector_addr, (mut contents, set)) in sectors {
+            let whole = !set.contains(&false);
+            if whole
+                && cache
+                    .as_ref()
+                    .is_some_and(|c| c.matches(sector_addr, &contents))
+            {
+                summary.sectors_skipped += 1;
+                continue;
             }
-            TargetID::Rp2350 => self
+            if differential || !whole {
+                let existing = self.conn.flash_read(sector_addr, PICO_SECTOR_SIZE).await?;
+                for ((byte, set), old) in contents.iter_mut().zip(set).zip(&existing) {
+                    if !set {
+                        *byte = *old;
+                    }
+                }
+                if contents == existing {
+                    summary.sectors_skipped += 1;
+                    if let Some(cache) = &mut cache {
+                        cache.insert(sector_addr, &contents);
+                    }
+                    continue;
+                }
+            }
+            summary.sectors_written += 1;
+            match runs.last_mut() {
+                Some((start, data)) if *start + data.len() as u32 == sector_addr => {
+                    data.extend_from_slice(&contents)
+                }
+                _ => runs.push((sector_addr, contents)),
+            }
+        }
+
+        let run_bytes: u64 = runs.iter().map(|(_, data)| data.len() as u64).sum();
+        let ram_bytes: u64 = ram.iter().map(|(_, data)| data.len() as u64).sum();
+
+        let total = self.start_phase(Phase::Erase, run_bytes);
+        let mut done = 0;
+        for (start, data) in &runs {
+            self.erase_sectors(*start, start + data.len() as u32, &mut done, total)
+                .await?;
+        }
+
+        let total = self.start_phase(Phase::Program, run_bytes + ram_bytes);
+        let mut done = 0;
+        for (start, data) in &runs {
+            for (i, window) in data.chunks(MAX_TRANSFER_SIZE).enumerate() {
+                let window_addr = start + (i * MAX_TRANSFER_SIZE) as u32;
+                // the erase already left blank pages as they should be, so only write
+                // the stretches of pages in between
+                let blank: Vec<bool> = window
+                    .chunks(PICO_PAGE_SIZE)
+                    .map(|page| page.iter().all(|&b| b == 0xff))
+                    .collect();
+                let mut offset = 0;
+                for pages in blank.chunk_by(|a, b| a == b) {
+                    let len = pages.len() * PICO_PAGE_SIZE;
+                    if !pages[0] {
+                        let stretch = window[offset..offset + len].to_vec();
+                        self.conn
+                            .flash_write(window_addr + offset as u32, stretch)
+                            .await?;
+                    }
+                    offset += len;
+                }
+                self.sectors_done(Phase::Program, window_addr, window.len(), &mut done, total);
+            }
+        }
+        for &(start, data) in &ram {
+            for (i, chunk) in data.chunks(MAX_TRANSFER_SIZE).enumerate() {
+                let chunk_addr = start + (i * MAX_TRANSFER_SIZE) as u32;
+                self.conn.flash_write(chunk_addr, chunk.to_vec()).await?;
+                self.sectors_done(Phase::Program, chunk_addr, chunk.len(), &mut done, total);
+            }
+        }
+
+        let total = self.start_phase(Phase::Verify, run_bytes + ram_bytes);
+        let mut done = 0;
+        for (start, data) in runs.iter().map(|(s, d)| (*s, d)).chain(ram) {
+            for (i, chunk) in data.chunks(MAX_TRANSFER_SIZE).enumerate() {
+                let chunk_addr = start + (i * MAX_TRANSFER_SIZE) as u32;
+                self.verify_range(chunk_addr, chunk).await?;
+                self.sectors_done(Phase::Verify, chunk_addr, chunk.len(), &mut done, total);
+            }
+        }
+
+        if let Some(mut cache) = cache {
+            for (start, data) in &runs {
+                for (i, contents) in data.chunks(sector_size).enumerate() {
+                    cache.insert(start + (i * sector_size) as u32, contents);
+                }
+            }
+            cache.save()?;
+        }
+        Ok(summary)
+    }
+
+    /// Refuse ranges that start in the flash window but run past the flash fitted, or
+    /// that are anywhere else but SRAM, before anything is written
+    async fn check_ranges(&mut self, ranges: &BTreeMap<u32, Vec<u8>>) -> Result<(), Error> {
+        let target = self.conn.get_device_type().ok_or(Error::NoDevice)?;
+        let sram = PICO_SRAM_START as u64..=target.sram_end() as u64;
+        for (&addr, data) in ranges {
+            let (start, end) = (addr as u64, addr as u64 + data.len() as u64);
+            let size = data.len();
+            if (PICO_FLASH_START..PICO_FLASH_END).contains(&addr) {
+                if end > self.flash_end().await? as u64 {
+                    return Err(Error::OutOfFlash { addr, size });
+                }
+            } else if !sram.contains(&start) || !sram.contains(&end) {
+                return Err(Error::OutOfMemory { addr, size });
+            }
+        }
+        Ok(())
+    }
+
+    /// The hashes cached for this device, if there is a cache directory and it has a serial
+    fn sector_cache(&self) -> Option<SectorCache> {
+        match (&self.cache_dir, self.conn.serial_number()) {
+            (Some(dir), Some(serial)) => Some(SectorCache::load(dir, &serial)),
+            _ => None,
+        }
+    }
+
+    /// Check the device still holds what the cache says it does in each sector the cache
+    /// would let us skip. Anything rewriting a sector erases it first, so reading back
+    /// the first page that isn't blank is enough to catch it
+    async fn spot_check(
+        &mut self,
+        cache: &SectorCache,
+        sectors: &BTreeMap<u32, (Vec<u8>, Vec<bool>)>,
+    ) -> Result<bool, Error> {
+        for (&addr, (contents, _)) in sectors {
+            if !cache.matches(addr, contents) {
+                continue;
+            }
+            let page = contents
+                .chunks(PICO_PAGE_SIZE)
+                .position(|page| page.iter().any(|&b| b != 0xff))
+                .unwrap_or(0);
+            let offset = page * PICO_PAGE_SIZE;
+            let expected = &contents[offset..offset + PICO_PAGE_SIZE];
+            let existing = self
                 .conn
-                .reboot2_normal(500)
-                .expect("failed to reboot device"),
+                .flash_read(addr + offset as u32, PICO_PAGE_SIZE as u32)
+                .await?;
+            if existing != expected {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    async fn verify_range(&mut self, addr: u32, expected: &[u8]) -> Result<(), Error> {
+        for (i, chunk) in expected.chunks(MAX_TRANSFER_SIZE).enumerate() {
+            let chunk_addr = addr + (i * MAX_TRANSFER_SIZE) as u32;
+            let read = self.conn.flash_read(chunk_addr, chunk.len() as u32).await?;
+            if let Some(offset) = chunk.iter().zip(&read).position(|(a, b)| a != b) {
+                return Err(Error::VerifyFailed {
+                    addr: chunk_addr + offset as u32,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Blocking wrapper around [`AsyncPicoTool`], running each operation to completion on
+/// the calling thread
+pub struct PicoTool<T: PicobootTransport = NusbTransport> {
+    inner: AsyncPicoTool<T>,
+}
+
+impl PicoTool<NusbTransport> {
+    pub fn new() -> Result<Self, Error> {
+        block_on(AsyncPicoTool::new()).map(AsyncPicoTool::into_blocking)
+    }
+
+    /// Connect to the BOOTSEL device that `selector` matches, retrying as
+    /// [`AsyncPicoTool::open`] does
+    pub fn open(selector: &DeviceSelector) -> Result<Self, Error> {
+        block_on(AsyncPicoTool::open(selector)).map(AsyncPicoTool::into_blocking)
+    }
+
+    /// See [`AsyncPicoTool::flash_many`], which this runs to completion on the calling
+    /// thread:
+    ///
+    /// ```no_run
+    /// # use picotool::{DeviceSelector, PicoTool};
+    /// # let path = std::path::Path::new("blink.uf2");
+    /// PicoTool::flash_many(&DeviceSelector::default(), |mut tool| async move {
+    ///     tool.load(path).await
+    /// })?;
+    /// # Ok::<(), picotool::Error>(())
+    /// ```
+    pub fn flash_many<F, Fut>(selector: &DeviceSelector, load: F) -> Result<Vec<FlashResult>, Error>
+    where
+        F: Fn(AsyncPicoTool) -> Fut,
+        Fut: Future<Output = Result<LoadSummary, Error>>,
+    {
+        block_on(AsyncPicoTool::flash_many(selector, load))
+    }
+}
+
+impl<T: PicobootTransport> PicoTool<T> {
+    pub fn with_transport(transport: T) -> Result<Self, Error> {
+        block_on(AsyncPicoTool::with_transport(transport)).map(AsyncPicoTool::into_blocking)
+    }
+
+    /// The async tool this wraps
+    pub fn into_async(self) -> AsyncPicoTool<T> {
+        self.inner
+    }
+
+    /// See [`AsyncPicoTool::set_differential`]
+    pub fn set_differential(&mut self, differential: bool) {
+        self.inner.set_differential(differential);
+    }
+
+    /// See [`AsyncPicoTool::set_cache_dir`]
+    pub fn set_cache_dir(&mut self, dir: Option<PathBuf>) {
+        self.inner.set_cache_dir(dir);
+    }
+
+    /// See [`AsyncPicoTool::set_progress`]
+    pub fn set_progress(&mut self, observer: Option<Box<dyn ProgressObserver + Send>>) {
+        self.inner.set_progress(observer);
+    }
+
+    /// See [`AsyncPicoTool::target`]
+    pub fn target(&self) -> Option<TargetID> {
+        self.inner.target()
+    }
+
+    /// See [`AsyncPicoTool::load`]
+    pub fn load(&mut self, path: &Path) -> Result<LoadSummary, Error> {
+        block_on(self.inner.load(path))
+    }
+
+    /// See [`AsyncPicoTool::flash_hex`]
+    pub fn flash_hex(&mut self, hex: &Path) -> Result<LoadSummary, Error> {
+        block_on(self.inner.flash_hex(hex))
+    }
+
+    /// See [`AsyncPicoTool::flash_bin`]
+    pub fn flash_bin(&mut self, bin: &Path, offset: u32) -> Result<LoadSummary, Error> {
+        block_on(self.inner.flash_bin(bin, offset))
+    }
+
+    /// See [`AsyncPicoTool::flash_elf`]
+    pub fn flash_elf(&mut self, elf: &Path) -> Result<LoadSummary, Error> {
+        block_on(self.inner.flash_elf(elf))
+    }
+
+    /// See [`AsyncPicoTool::flash_uf2`]
+    pub fn flash_uf2(&mut self, uf2: &Path) -> Result<LoadSummary, Error> {
+        block_on(self.inner.flash_uf2(uf2))
+    }
+
+    /// See [`AsyncPicoTool::flash_uf2_family`]
+    pub fn flash_uf2_family(
+        &mut self,
+        uf2: &Path,
+        family: Uf2Family,
+    ) -> Result<LoadSummary, Error> {
+        block_on(self.inner.flash_uf2_family(uf2, family))
+    }
+
+    /// See [`AsyncPicoTool::verify`]
+    pub fn verify(&mut self, path: &Path) -> Result<VerifyReport, Error> {
+        block_on(self.inner.verify(path))
+    }
+
+    /// See [`AsyncPicoTool::verify_bin`]
+    pub fn verify_bin(&mut self, bin: &Path, offset: u32) -> Result<VerifyReport, Error> {
+        block_on(self.inner.verify_bin(bin, offset))
+    }
+
+    /// See [`AsyncPicoTool::verify_uf2_family`]
+    pub fn verify_uf2_family(
+        &mut self,
+        uf2: &Path,
+        family: Uf2Family,
+    ) -> Result<VerifyReport, Error> {
+        block_on(self.inner.verify_uf2_family(uf2, family))
+    }
+
+    /// See [`AsyncPicoTool::save`]
+    pub fn save(
+        &mut self,
+        range: SaveRange,
+        format: SaveFormat,
+        path: &Path,
+    ) -> Result<Range<u32>, Error> {
+        block_on(self.inner.save(range, format, path))
+    }
+
+    /// See [`AsyncPicoTool::read_memory`]
+    pub fn read_memory(&mut self, range: SaveRange) -> Result<(u32, Vec<u8>), Error> {
+        block_on(self.inner.read_memory(range))
+    }
+
+    /// See [`AsyncPicoTool::erase`]
+    pub fn erase(&mut self, range: EraseRange, blank_check: bool) -> Result<Range<u32>, Error> {
+        block_on(self.inner.erase(range, blank_check))
+    }
+
+    /// See [`AsyncPicoTool::reboot`]
+    pub fn reboot(
+        &mut self,
+        mode: RebootMode,
+        delay_ms: u32,
+        no_return: bool,
+    ) -> Result<(), Error> {
+        block_on(self.inner.reboot(mode, delay_ms, no_return))
+    }
+
+    /// See [`AsyncPicoTool::reboot2`]
+    pub fn reboot2(&mut self, reboot: Reboot2) -> Result<(), Error> {
+        block_on(self.inner.reboot2(reboot))
+    }
+}
+
+/// The kinds of file PicoTool can load
+enum FileKind {
+    Elf,
+    Hex,
+    Bin,
+    Uf2,
+}
+
+impl FileKind {
+    /// Files ending in `.bin` are binaries whatever they hold. Otherwise ELF files are
+    /// recognised by their contents, as are HEX files ending in `.hex` or `.ihex` or
+    /// with no extension, and anything else is taken to be a UF2
+    fn of(path: &Path) -> Result<Self, Error> {
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        let is = |name: &str| extension.is_some_and(|ext| ext.eq_ignore_ascii_case(name));
+        if is("bin") {
+            return Ok(FileKind::Bin);
+        }
+        let mut magic = [0; 4];
+        let read = File::open(path)?.read(&mut magic)?;
+        let may_be_hex = extension.is_none() || is("hex") || is("ihex");
+        Ok(if &magic[..read] == ELF_MAGIC {
+            FileKind::Elf
+        } else if may_be_hex && magic[..read].starts_with(b":") {
+            FileKind::Hex
+        } else {
+            FileKind::Uf2
+        })
+    }
+}
+
+/// Poll all of `futures` on the current task until every one has finished, returning
+/// their outputs in order
+async fn join_all<F: Future>(futures: Vec<F>) -> Vec<F::Output> {
+    let mut futures: Vec<_> = futures.into_iter().map(|f| Some(Box::pin(f))).collect();
+    let mut outputs: Vec<Option<F::Output>> = futures.iter().map(|_| None).collect();
+    std::future::poll_fn(|cx| {
+        let mut pending = false;
+        for (future, output) in futures.iter_mut().zip(&mut outputs) {
+            if let Some(f) = future {
+                match f.as_mut().poll(cx) {
+                    Poll::Ready(out) => {
+                        *output = Some(out);
+                        *future = None;
+                    }
+                    Poll::Pending => pending = true,
+                }
+            }
         }
-        println!("Flash success!");
+        if pending {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    })
+    .await;
+    outputs
+        .into_iter()
+        .map(|out| out.expect("every future has finished"))
+        .collect()
+}
+
+/// The interfaces a BOOTSEL reboot should leave out, as both chips' bootroms take them
+fn bootsel_disable_mask(disable_msd: bool, disable_picoboot: bool) -> u32 {
+    let mut mask = 0;
+    if disable_msd {
+        mask |= BOOTSEL_DISABLE_MSD;
+    }
+    if disable_picoboot {
+        mask |= BOOTSEL_DISABLE_PICOBOOT;
+    }
+    mask
+}
+
+fn hex_ranges(hex: &Path) -> Result<BTreeMap<u32, Vec<u8>>, Error> {
+    Ok(parse_ihex(&std::fs::read(hex)?)?)
+}
+
+/// A binary file placed `offset` bytes into flash, or at `offset` if that is a flash
+/// address, which must fit before `flash_end`
+fn bin_ranges(bin: &Path, offset: u32, flash_end: u32) -> Result<BTreeMap<u32, Vec<u8>>, Error> {
+    let data = std::fs::read(bin)?;
+    let addr = if (PICO_FLASH_START..PICO_FLASH_END).contains(&offset) {
+        offset
+    } else {
+        PICO_FLASH_START.saturating_add(offset)
+    };
+    if addr as u64 + data.len() as u64 > flash_end as u64 {
+        return Err(Error::OutOfFlash {
+            addr,
+            size: data.len(),
+        });
+    }
+    Ok(BTreeMap::from([(addr, data)]))
+}
+
+/// The ranges of the UF2 image for `family`, or if none is given the one best suited
+/// to `target` along with any absolute and data images
+fn uf2_ranges(
+    uf2: &Path,
+    family: Option<Uf2Family>,
+    target: TargetID,
+) -> Result<BTreeMap<u32, Vec<u8>>, Error> {
+    let images = parse_uf2(&std::fs::read(uf2)?)?;
+    let image = match family {
+        Some(family) => images
+            .iter()
+            .find(|i| i.family_id == Some(family.id()))
+            .cloned(),
+        None => select_image(&images, target)?,
+    };
+    let image = image.ok_or_else(|| Error::FamilyMismatch {
+        target,
+        requested: family,
+        found: images.iter().map(|i| i.family_id).collect(),
+    })?;
+    Ok(image.ranges())
+}
+
+#[cfg(all(test, feature = "emulator"))]
+mod tests {
+    use super::*;
+    use crate::picoboot::cmd::PicobootStatus;
+    use crate::picoboot::emulator::EmulatedDevice;
+    use futures_lite::future::yield_now;
+    use std::cell::RefCell;
+
+    #[test]
+    fn join_all_polls_every_future_each_time() {
+        let steps = RefCell::new(vec![]);
+        let task = |id: usize, len: usize| {
+            let steps = &steps;
+            async move {
+                for step in 0..len {
+                    steps.borrow_mut().push((id, step));
+                    yield_now().await;
+                }
+                id
+            }
+        };
+        // the first finishes last, but its output still comes first
+        let outputs = block_on(join_all(vec![task(0, 3), task(1, 1), task(2, 2)]));
+        assert_eq!(outputs, [0, 1, 2]);
+        assert_eq!(
+            steps.into_inner(),
+            [(0, 0), (1, 0), (2, 0), (0, 1), (2, 1), (0, 2)]
+        );
+    }
+
+    #[test]
+    fn join_all_keeps_each_boards_result() {
+        let dir = std::env::temp_dir().join(format!("picotool-{}-join-all", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("image.bin");
+        let data: Vec<u8> = (0..3 * PICO_SECTOR_SIZE).map(|i| (i * 7) as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        let good = EmulatedDevice::new(TargetID::Rp2040);
+        let bad = EmulatedDevice::new(TargetID::Rp2350);
+        good.set_timing(picoboot::emulator::Timing::typical());
+        let loads = [good.clone(), bad.clone()].map(|device| {
+            let path = &path;
+            async move {
+                let mut tool = AsyncPicoTool::with_transport(device).await?;
+                tool.flash_bin(path, 0).await
+            }
+        });
+        // the board that fails does so straight away, and the other carries on
+        bad.fail_next_command(PicobootStatus::NotPermitted);
+        let results = block_on(join_all(loads.into()));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(results[0].as_ref().unwrap().sectors_written, 3);
+        assert_eq!(good.flash()[..data.len()], data);
+        assert!(matches!(
+            results[1],
+            Err(Error::Status {
+                status: PicobootStatus::NotPermitted,
+                ..
+            })
+        ));
+        assert!(bad.flash().iter().all(|&b| b == 0xff));
     }
 }
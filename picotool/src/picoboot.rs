@@ -1,2 +1,4 @@
 pub mod cmd;
+#[cfg(feature = "emulator")]
+pub mod emulator;
 pub mod usb;
@@ -1,8 +1,29 @@
+use crate::{Error, TargetID, PICO_FLASH_END, PICO_FLASH_START, PICO_SECTOR_SIZE, PICO_SRAM_START};
 use serde::{Deserialize, Serialize};
-const PICOBOOT_MAGIC: u32 = 0x431FD10B;
+pub(crate) const PICOBOOT_MAGIC: u32 = 0x431FD10B;
+
+// see the rp2350 datasheet section 5.4.8.24 for the reboot types and flags
+const REBOOT2_TYPE_NORMAL: u32 = 0x0;
+const REBOOT2_TYPE_BOOTSEL: u32 = 0x2;
+const REBOOT2_TYPE_RAM_IMAGE: u32 = 0x3;
+const REBOOT2_TYPE_FLASH_UPDATE: u32 = 0x4;
+const REBOOT2_TYPE_PC_SP: u32 = 0xd;
+const REBOOT2_TO_ARM: u32 = 0x10;
+const REBOOT2_TO_RISCV: u32 = 0x20;
+const REBOOT2_NO_RETURN_ON_SUCCESS: u32 = 0x100;
+// BOOTSEL reboots take these in p0, and the activity LED pin in p1. The RP2040's
+// reset_usb_boot takes the same two interface bits
+pub(crate) const BOOTSEL_DISABLE_MSD: u32 = 0x01;
+pub(crate) const BOOTSEL_DISABLE_PICOBOOT: u32 = 0x02;
+const BOOTSEL_GPIO_ACTIVE_LOW: u32 = 0x10;
+const BOOTSEL_GPIO_SPECIFIED: u32 = 0x20;
+// GPIOs on the larger RP2350 package
+const RP2350_GPIO_COUNT: u8 = 48;
+// GET_INFO's type for get_sys_info, which takes its flags in the first parameter
+pub(crate) const GET_INFO_SYS: u8 = 1;
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum PicobootCmdId {
     Unknown = 0x0,
     ExclusiveAccess = 0x1,
@@ -46,9 +67,10 @@ impl TryFrom<u8> for PicobootCmdId {
     }
 }
 
+/// Status codes reported by the bootrom in response to a PICOBOOT command
 #[repr(u32)]
-#[derive(Debug)]
-pub(crate) enum PicobootStatus {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PicobootStatus {
     Ok = 0,
     UnknownCmd = 1,
     InvalidCmdLength = 2,
@@ -95,12 +117,37 @@ impl TryFrom<u32> for PicobootStatus {
         }
     }
 }
+impl std::fmt::Display for PicobootStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msg = match self {
+            Self::Ok => "ok",
+            Self::UnknownCmd => "unknown command",
+            Self::InvalidCmdLength => "invalid command length",
+            Self::InvalidTransferLength => "invalid transfer length",
+            Self::InvalidAddress => "invalid address",
+            Self::BadAlignment => "bad alignment",
+            Self::InterleavedWrite => "interleaved write",
+            Self::Rebooting => "rebooting",
+            Self::UnknownError => "unknown error",
+            Self::InvalidState => "invalid state",
+            Self::NotPermitted => "not permitted",
+            Self::InvalidArg => "invalid argument",
+            Self::BufferTooSmall => "buffer too small",
+            Self::PreconditionNotMet => "precondition not met",
+            Self::ModifiedData => "modified data",
+            Self::InvalidData => "invalid data",
+            Self::NotFound => "not found",
+            Self::UnsupportedModification => "unsupported modification",
+        };
+        f.write_str(msg)
+    }
+}
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 #[repr(C, packed)]
 pub(crate) struct PicobootRangeCmd {
-    addr: u32,
-    size: u32,
+    pub addr: u32,
+    pub size: u32,
     _unused: u64,
 }
 impl PicobootRangeCmd {
@@ -117,14 +164,19 @@ impl PicobootRangeCmd {
                 panic!("Expected a Vec of length {} but it was {}", 16, v.len())
             })
     }
+
+    #[cfg(feature = "emulator")]
+    pub fn de(args: &[u8; 16]) -> Self {
+        bincode::deserialize(args).expect("PicobootRangeCmd is exactly 16 bytes")
+    }
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 #[repr(C, packed)]
 pub(crate) struct PicobootRebootCmd {
-    pc: u32,
-    sp: u32,
-    delay: u32,
+    pub pc: u32,
+    pub sp: u32,
+    pub delay: u32,
     _unused: u32,
 }
 impl PicobootRebootCmd {
@@ -142,15 +194,20 @@ impl PicobootRebootCmd {
                 panic!("Expected a Vec of length {} but it was {}", 16, v.len())
             })
     }
+
+    #[cfg(feature = "emulator")]
+    pub fn de(args: &[u8; 16]) -> Self {
+        bincode::deserialize(args).expect("PicobootRebootCmd is exactly 16 bytes")
+    }
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 #[repr(C, packed)]
 pub(crate) struct PicobootReboot2Cmd {
-    flags: u32,
-    delay: u32,
-    p0: u32,
-    p1: u32,
+    pub flags: u32,
+    pub delay: u32,
+    pub p0: u32,
+    pub p1: u32,
 }
 impl PicobootReboot2Cmd {
     pub fn ser(flags: u32, delay: u32, p0: u32, p1: u32) -> [u8; 16] {
@@ -167,9 +224,203 @@ impl PicobootReboot2Cmd {
                 panic!("Expected a Vec of length {} but it was {}", 16, v.len())
             })
     }
+
+    #[cfg(feature = "emulator")]
+    pub fn de(args: &[u8; 16]) -> Self {
+        bincode::deserialize(args).expect("PicobootReboot2Cmd is exactly 16 bytes")
+    }
+}
+
+/// The instruction set an RP2350 switches its cores to as it reboots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Architecture {
+    Arm,
+    RiscV,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reboot2Type {
+    Normal,
+    Bootsel,
+    RamImage { start: u32, size: u32 },
+    FlashUpdate { buffer: u32 },
+    PcSp { pc: u32, sp: u32 },
+}
+
+/// An RP2350 REBOOT2 command. Start from the kind of reboot and add options to it:
+///
+/// `Reboot2::bootsel().disable_msd().activity_led(25, false).delay(100)`
+///
+/// The options are checked against each other when the command is sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reboot2 {
+    reboot_type: Reboot2Type,
+    disable_msd: bool,
+    disable_picoboot: bool,
+    activity_led: Option<(u8, bool)>,
+    arch: Option<Architecture>,
+    no_return: bool,
+    delay_ms: u32,
+}
+
+impl Reboot2 {
+    fn new(reboot_type: Reboot2Type) -> Self {
+        Reboot2 {
+            reboot_type,
+            disable_msd: false,
+            disable_picoboot: false,
+            activity_led: None,
+            arch: None,
+            no_return: false,
+            delay_ms: 0,
+        }
+    }
+
+    /// Boot as if from power on
+    pub fn normal() -> Self {
+        Self::new(Reboot2Type::Normal)
+    }
+
+    /// Come back up in BOOTSEL mode
+    pub fn bootsel() -> Self {
+        Self::new(Reboot2Type::Bootsel)
+    }
+
+    /// Boot the image found in the `size` bytes at `start` in SRAM. Both must be
+    /// multiples of 4KiB
+    pub fn ram_image(start: u32, size: u32) -> Self {
+        Self::new(Reboot2Type::RamImage { start, size })
+    }
+
+    /// Boot normally, treating the image at `buffer` in flash as one that has just
+    /// been written, so its version can be checked and committed
+    pub fn flash_update(buffer: u32) -> Self {
+        Self::new(Reboot2Type::FlashUpdate { buffer })
+    }
+
+    /// Start running at `pc` with the stack pointer at `sp`
+    pub fn pc_sp(pc: u32, sp: u32) -> Self {
+        Self::new(Reboot2Type::PcSp { pc, sp })
+    }
+
+    /// BOOTSEL only: leave out the USB mass storage interface
+    pub fn disable_msd(mut self) -> Self {
+        self.disable_msd = true;
+        self
+    }
+
+    /// BOOTSEL only: leave out the PICOBOOT interface
+    pub fn disable_picoboot(mut self) -> Self {
+        self.disable_picoboot = true;
+        self
+    }
+
+    /// BOOTSEL only: drive GPIO `pin` while the bootloader is busy, low if `active_low`
+    pub fn activity_led(mut self, pin: u8, active_low: bool) -> Self {
+        self.activity_led = Some((pin, active_low));
+        self
+    }
+
+    /// Switch the cores to `arch` as part of the reboot
+    pub fn arch(mut self, arch: Architecture) -> Self {
+        self.arch = Some(arch);
+        self
+    }
+
+    /// Set the no-return-on-success flag
+    pub fn no_return(mut self) -> Self {
+        self.no_return = true;
+        self
+    }
+
+    /// Wait `delay_ms` before rebooting. None by default
+    pub fn delay(mut self, delay_ms: u32) -> Self {
+        self.delay_ms = delay_ms;
+        self
+    }
+
+    /// Check that the options go together, as the bootrom would only reject some of them
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |reason| Err(Error::InvalidReboot(reason));
+        let bootsel_options =
+            self.disable_msd || self.disable_picoboot || self.activity_led.is_some();
+        match self.reboot_type {
+            Reboot2Type::Normal => {}
+            Reboot2Type::Bootsel => {
+                if self.disable_msd && self.disable_picoboot {
+                    return invalid("BOOTSEL mode needs mass storage or PICOBOOT left enabled");
+                }
+                if matches!(self.activity_led, Some((pin, _)) if pin >= RP2350_GPIO_COUNT) {
+                    return invalid("the activity LED must be on one of GPIOs 0-47");
+                }
+            }
+            Reboot2Type::RamImage { start, size } => {
+                if start % PICO_SECTOR_SIZE != 0 || size % PICO_SECTOR_SIZE != 0 {
+                    return invalid("a RAM image must start and end on a 4KiB boundary");
+                }
+                let sram = PICO_SRAM_START..=TargetID::Rp2350.sram_end();
+                let end = start.checked_add(size);
+                if size == 0 || !sram.contains(&start) || !end.is_some_and(|e| sram.contains(&e)) {
+                    return invalid("a RAM image must be in SRAM");
+                }
+            }
+            Reboot2Type::FlashUpdate { buffer } => {
+                if !(PICO_FLASH_START..PICO_FLASH_END).contains(&buffer) {
+                    return invalid("a flash update buffer must be in flash");
+                }
+            }
+            Reboot2Type::PcSp { sp, .. } => {
+                if sp % 4 != 0 {
+                    return invalid("the stack pointer must be word aligned");
+                }
+            }
+        }
+        if bootsel_options && self.reboot_type != Reboot2Type::Bootsel {
+            return invalid("interface and activity LED options only apply to BOOTSEL");
+        }
+        Ok(())
+    }
+
+    /// The command arguments, once the options are known to go together
+    pub(crate) fn args(&self) -> Result<[u8; 16], Error> {
+        self.validate()?;
+        let (reboot_type, p0, p1) = match self.reboot_type {
+            Reboot2Type::Normal => (REBOOT2_TYPE_NORMAL, 0, 0),
+            Reboot2Type::Bootsel => {
+                let mut p0 = 0;
+                if self.disable_msd {
+                    p0 |= BOOTSEL_DISABLE_MSD;
+                }
+                if self.disable_picoboot {
+                    p0 |= BOOTSEL_DISABLE_PICOBOOT;
+                }
+                let (pin, active_low) = self.activity_led.unwrap_or_default();
+                if self.activity_led.is_some() {
+                    p0 |= BOOTSEL_GPIO_SPECIFIED;
+                }
+                if active_low {
+                    p0 |= BOOTSEL_GPIO_ACTIVE_LOW;
+                }
+                (REBOOT2_TYPE_BOOTSEL, p0, pin as u32)
+            }
+            Reboot2Type::RamImage { start, size } => (REBOOT2_TYPE_RAM_IMAGE, start, size),
+            Reboot2Type::FlashUpdate { buffer } => (REBOOT2_TYPE_FLASH_UPDATE, buffer, 0),
+            Reboot2Type::PcSp { pc, sp } => (REBOOT2_TYPE_PC_SP, pc, sp),
+        };
+        let mut flags = reboot_type;
+        flags |= match self.arch {
+            Some(Architecture::Arm) => REBOOT2_TO_ARM,
+            Some(Architecture::RiscV) => REBOOT2_TO_RISCV,
+            None => 0,
+        };
+        if self.no_return {
+            flags |= REBOOT2_NO_RETURN_ON_SUCCESS;
+        }
+        Ok(PicobootReboot2Cmd::ser(flags, self.delay_ms, p0, p1))
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 #[repr(C, packed)]
 pub(crate) struct PicobootStatusCmd {
     pub token: u32,
@@ -178,17 +429,35 @@ pub(crate) struct PicobootStatusCmd {
     pub in_progress: u8,
     _unused: [u8; 6],
 }
+impl PicobootStatusCmd {
+    #[cfg(feature = "emulator")]
+    pub fn ser(token: u32, status_code: u32, cmd_id: u8, in_progress: u8) -> [u8; 16] {
+        let c = PicobootStatusCmd {
+            token,
+            status_code,
+            cmd_id,
+            in_progress,
+            _unused: [0; 6],
+        };
+        bincode::serialize(&c)
+            .unwrap()
+            .try_into()
+            .unwrap_or_else(|v: Vec<u8>| {
+                panic!("Expected a Vec of length {} but it was {}", 16, v.len())
+            })
+    }
+}
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 #[repr(C, packed)]
 pub(crate) struct PicobootCmd {
-    magic: u32,
+    pub magic: u32,
     pub token: u32,
     pub cmd_id: u8,
-    cmd_size: u8,
+    pub cmd_size: u8,
     _unused: u16,
     pub transfer_len: u32,
-    args: [u8; 16],
+    pub args: [u8; 16],
 }
 impl PicobootCmd {
     pub fn new(cmd_id: PicobootCmdId, cmd_size: u8, transfer_len: u32, args: [u8; 16]) -> Self {
@@ -204,3 +473,112 @@ impl PicobootCmd {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The flags, delay, p0 and p1 words a REBOOT2 command sends
+    fn words(reboot: Reboot2) -> [u32; 4] {
+        let args = reboot.args().unwrap();
+        std::array::from_fn(|i| u32::from_le_bytes(args[i * 4..i * 4 + 4].try_into().unwrap()))
+    }
+
+    fn reason(reboot: Reboot2) -> &'static str {
+        match reboot.args() {
+            Err(Error::InvalidReboot(reason)) => reason,
+            other => panic!("expected {reboot:?} to be refused, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn reboot2_args() {
+        assert_eq!(words(Reboot2::normal().delay(100)), [0x0, 100, 0, 0]);
+        assert_eq!(words(Reboot2::bootsel()), [0x2, 0, 0, 0]);
+        assert_eq!(
+            words(Reboot2::bootsel().disable_msd().activity_led(25, true)),
+            [0x2, 0, 0x1 | 0x10 | 0x20, 25]
+        );
+        assert_eq!(
+            words(
+                Reboot2::bootsel()
+                    .disable_picoboot()
+                    .activity_led(47, false)
+            ),
+            [0x2, 0, 0x2 | 0x20, 47]
+        );
+        assert_eq!(
+            words(Reboot2::ram_image(0x2000_1000, 0x2000)),
+            [0x3, 0, 0x2000_1000, 0x2000]
+        );
+        assert_eq!(
+            words(Reboot2::flash_update(0x1010_0000)),
+            [0x4, 0, 0x1010_0000, 0]
+        );
+        assert_eq!(
+            words(Reboot2::pc_sp(0x2000_0101, 0x2008_0000)),
+            [0xd, 0, 0x2000_0101, 0x2008_0000]
+        );
+        assert_eq!(
+            words(Reboot2::normal().arch(Architecture::RiscV).no_return()),
+            [0x20 | 0x100, 0, 0, 0]
+        );
+        assert_eq!(
+            words(Reboot2::bootsel().arch(Architecture::Arm)),
+            [0x2 | 0x10, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn bootsel_options_need_bootsel() {
+        for reboot in [
+            Reboot2::normal(),
+            Reboot2::ram_image(0x2000_0000, 0x1000),
+            Reboot2::flash_update(0x1000_0000),
+            Reboot2::pc_sp(0x2000_0101, 0x2000_4000),
+        ] {
+            for with_option in [
+                reboot.disable_msd(),
+                reboot.disable_picoboot(),
+                reboot.activity_led(25, false),
+            ] {
+                assert_eq!(
+                    reason(with_option),
+                    "interface and activity LED options only apply to BOOTSEL"
+                );
+            }
+        }
+        assert_eq!(
+            reason(Reboot2::bootsel().disable_msd().disable_picoboot()),
+            "BOOTSEL mode needs mass storage or PICOBOOT left enabled"
+        );
+        assert_eq!(
+            reason(Reboot2::bootsel().activity_led(48, false)),
+            "the activity LED must be on one of GPIOs 0-47"
+        );
+    }
+
+    #[test]
+    fn reboot_arguments_are_checked() {
+        let unaligned = "a RAM image must start and end on a 4KiB boundary";
+        let outside = "a RAM image must be in SRAM";
+        for (start, size, expected) in [
+            (0x2000_0800, 0x1000, unaligned),
+            (0x2000_0000, 0x800, unaligned),
+            (0x2000_0000, 0, outside),
+            (0x1000_0000, 0x1000, outside),
+            (0x2008_1000, 0x2000, outside),
+            (0xffff_f000, 0x2000, outside),
+        ] {
+            assert_eq!(reason(Reboot2::ram_image(start, size)), expected);
+        }
+        assert_eq!(
+            reason(Reboot2::flash_update(0x2000_0000)),
+            "a flash update buffer must be in flash"
+        );
+        assert_eq!(
+            reason(Reboot2::pc_sp(0x2000_0101, 0x2000_4002)),
+            "the stack pointer must be word aligned"
+        );
+    }
+}
@@ -0,0 +1,729 @@
+// An in-process model of the RP2040/RP2350 bootrom's PICOBOOT interface, for
+// exercising the command layer and PicoTool without a board attached.
+
+// see https://datasheets.raspberrypi.com/rp2040/rp2040-datasheet.pdf
+// section 2.8.5 and the rp2350 datasheet section 5.6 for the behaviour modelled here
+
+use crate::flash_size::{jedec_id_program, JEDEC_ID_RESULT_OFFSET, SYS_INFO_FLASH_DEV_INFO};
+use crate::picoboot::cmd::*;
+use crate::picoboot::usb::PicobootTransport;
+use crate::{Error, TargetID, PICO_FLASH_END, PICO_FLASH_START, PICO_PAGE_SIZE, PICO_SECTOR_SIZE};
+use async_io::Timer;
+use nusb::transfer::TransferError;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+const SRAM_START: u32 = 0x20000000;
+const SRAM_SIZE_RP2040: usize = 264 * 1024;
+const SRAM_SIZE_RP2350: usize = 520 * 1024;
+const FLASH_SIZE_MAX: usize = 16 * 1024 * 1024;
+// flash fitted to the Pico and Pico 2 respectively
+const FLASH_SIZE_RP2040: usize = 2 * 1024 * 1024;
+const FLASH_SIZE_RP2350: usize = 4 * 1024 * 1024;
+// the bootrom erases aligned 64KiB blocks with a single block erase
+const FLASH_BLOCK_SIZE: usize = 64 * 1024;
+
+const REBOOT2_TYPE_MASK: u32 = 0xf;
+const REBOOT2_TYPE_NORMAL: u32 = 0x0;
+const REBOOT2_TYPE_BOOTSEL: u32 = 0x2;
+const REBOOT2_TYPE_RAM_IMAGE: u32 = 0x3;
+const REBOOT2_TYPE_FLASH_UPDATE: u32 = 0x4;
+const REBOOT2_TYPE_PC_SP: u32 = 0xd;
+const REBOOT2_TO_ARM: u32 = 0x10;
+const REBOOT2_TO_RISCV: u32 = 0x20;
+
+/// Exclusive access level most recently requested by the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExclusiveAccess {
+    NotExclusive,
+    Exclusive,
+    ExclusiveAndEject,
+}
+
+/// How the emulated flash is currently attached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XipState {
+    /// Flash is memory mapped. This is the state out of reset and after ENTER_CMD_XIP
+    Xip,
+    /// Flash is in serial command mode after EXIT_XIP, ready to program and erase
+    Serial,
+}
+
+/// A reboot the host asked for, recorded once the command has been acknowledged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebootRequest {
+    /// RP2040 REBOOT command
+    Reboot { pc: u32, sp: u32, delay: u32 },
+    /// RP2350 REBOOT2 command
+    Reboot2 {
+        flags: u32,
+        delay: u32,
+        p0: u32,
+        p1: u32,
+    },
+}
+
+/// How long the emulated device takes over each operation, so the host side can be
+/// benchmarked. Everything is instant by default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timing {
+    /// Round trip of each bulk or control transfer
+    pub transfer: Duration,
+    pub sector_erase: Duration,
+    /// Erasing an aligned 64KiB block, which the bootrom does in place of 16 sector erases
+    pub block_erase: Duration,
+    pub page_program: Duration,
+}
+
+impl Timing {
+    /// Rough figures for a full speed USB link and the W25Q16JV flash on a Pico
+    pub const fn typical() -> Self {
+        Timing {
+            transfer: Duration::from_micros(500),
+            sector_erase: Duration::from_millis(45),
+            block_erase: Duration::from_millis(150),
+            page_program: Duration::from_micros(400),
+        }
+    }
+}
+
+/// An emulated RP2040 or RP2350 sitting in BOOTSEL mode.
+///
+/// Implements [`PicobootTransport`], so it can be handed to
+/// [`PicoTool::with_transport`](crate::PicoTool::with_transport) in place of a USB device.
+/// Clones share the same device, so keep one around to inspect memory after the
+/// tool has taken ownership of the other.
+///
+/// Commands complete instantly unless [`EmulatedDevice::set_timing`] says otherwise,
+/// in which case their futures wait for as long as they would take. A rejected
+/// command sets its status code and stalls the bulk endpoints until the host resets
+/// the interface, as the bootrom does.
+/// The flash size can be asked for, with GET_INFO on the RP2350 or by running the
+/// crate's JEDEC ID program on the RP2040, but only when it is a power of two from
+/// 64KiB up, as a real flash part's is. Other code sent to EXEC is never run, the
+/// other GET_INFO types and OTP commands report `UnknownCmd`, and the ROM reads as
+/// zeros.
+#[derive(Clone)]
+pub struct EmulatedDevice {
+    state: Arc<Mutex<State>>,
+}
+
+impl EmulatedDevice {
+    /// An erased device with the flash size of the matching Raspberry Pi board
+    pub fn new(target_id: TargetID) -> Self {
+        let flash_size = match target_id {
+            TargetID::Rp2040 => FLASH_SIZE_RP2040,
+            TargetID::Rp2350 => FLASH_SIZE_RP2350,
+        };
+        Self::with_flash_size(target_id, flash_size)
+    }
+
+    /// An erased device with `flash_size` bytes of flash
+    pub fn with_flash_size(target_id: TargetID, flash_size: usize) -> Self {
+        assert!(
+            flash_size <= FLASH_SIZE_MAX && flash_size.is_multiple_of(PICO_SECTOR_SIZE as usize),
+            "flash size must be a whole number of sectors, up to 16MiB"
+        );
+        let sram_size = match target_id {
+            TargetID::Rp2040 => SRAM_SIZE_RP2040,
+            TargetID::Rp2350 => SRAM_SIZE_RP2350,
+        };
+        let state = State {
+            target_id,
+            flash: vec![0xff; flash_size],
+            sram: vec![0; sram_size],
+            rom: vec![0; target_id.rom_end() as usize],
+            exclusive: ExclusiveAccess::NotExclusive,
+            xip: XipState::Xip,
+            phase: Phase::Command,
+            status: CommandStatus::default(),
+            pending_reboot: None,
+            reboot: None,
+            injected: None,
+            misreport: false,
+            erased: vec![],
+            written: vec![],
+            serial_number: None,
+            timing: Timing::default(),
+            busy: Duration::ZERO,
+        };
+        EmulatedDevice {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Run one transfer against the device, then wait as long as it and the work it
+    /// set off would take. The lock is released for the wait
+    async fn transfer<R>(&self, f: impl FnOnce(&mut State) -> R) -> R {
+        let (result, delay) = {
+            let mut state = self.lock();
+            let result = f(&mut state);
+            let delay = state.timing.transfer + std::mem::take(&mut state.busy);
+            (result, delay)
+        };
+        if !delay.is_zero() {
+            Timer::after(delay).await;
+        }
+        result
+    }
+
+    /// A copy of the whole flash array, starting at `PICO_FLASH_START`
+    pub fn flash(&self) -> Vec<u8> {
+        self.lock().flash.clone()
+    }
+
+    /// A copy of the whole SRAM array, starting at 0x20000000
+    pub fn sram(&self) -> Vec<u8> {
+        self.lock().sram.clone()
+    }
+
+    /// Overwrite flash at `addr` directly, bypassing erase semantics
+    pub fn load_flash(&self, addr: u32, data: &[u8]) {
+        let mut state = self.lock();
+        let offset = (addr - PICO_FLASH_START) as usize;
+        state.flash[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    pub fn exclusive_access(&self) -> ExclusiveAccess {
+        self.lock().exclusive
+    }
+
+    pub fn xip_state(&self) -> XipState {
+        self.lock().xip
+    }
+
+    /// The reboot the device has performed, if any. A rebooting device rejects
+    /// every further command with `Rebooting`
+    pub fn reboot_requested(&self) -> Option<RebootRequest> {
+        self.lock().reboot
+    }
+
+    /// Report `serial` as the device's USB serial number. There is none by default
+    pub fn set_serial_number(&self, serial: &str) {
+        self.lock().serial_number = Some(serial.to_string());
+    }
+
+    pub fn set_timing(&self, timing: Timing) {
+        self.lock().timing = timing;
+    }
+
+    /// Reject the next command with `status`, for statuses the model never raises on
+    /// its own
+    pub fn fail_next_command(&self, status: PicobootStatus) {
+        self.lock().injected = Some(status);
+    }
+
+    /// Report the next command's status with the token of the one before, as a device
+    /// that lost track of a command would
+    pub fn misreport_next_status(&self) {
+        self.lock().misreport = true;
+    }
+
+    /// The flash ranges erased since the last call, one per FLASH_ERASE command
+    pub fn take_erased(&self) -> Vec<Range<u32>> {
+        std::mem::take(&mut self.lock().erased)
+    }
+
+    /// The flash ranges programmed since the last call, one per WRITE command
+    pub fn take_written(&self) -> Vec<Range<u32>> {
+        std::mem::take(&mut self.lock().written)
+    }
+}
+
+impl PicobootTransport for EmulatedDevice {
+    async fn bulk_in(&mut self, len: usize) -> Result<Vec<u8>, Error> {
+        self.transfer(|state| state.bulk_in(len)).await
+    }
+
+    async fn bulk_out(&mut self, buf: Vec<u8>) -> Result<usize, Error> {
+        self.transfer(|state| state.bulk_out(buf)).await
+    }
+
+    async fn reset_interface(&mut self) -> Result<(), Error> {
+        let mut state = self.lock();
+        state.phase = Phase::Command;
+        state.status = CommandStatus::default();
+        Ok(())
+    }
+
+    async fn command_status(&mut self) -> Result<Vec<u8>, Error> {
+        let status = self.transfer(|state| state.status).await;
+        Ok(PicobootStatusCmd::ser(status.token, status.code as u32, status.cmd_id, 0).to_vec())
+    }
+
+    fn target_id(&self) -> TargetID {
+        self.lock().target_id
+    }
+
+    fn serial_number(&self) -> Option<String> {
+        self.lock().serial_number.clone()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CommandStatus {
+    token: u32,
+    code: PicobootStatus,
+    cmd_id: u8,
+}
+
+impl Default for CommandStatus {
+    fn default() -> Self {
+        CommandStatus {
+            token: 0,
+            code: PicobootStatus::Ok,
+            cmd_id: 0,
+        }
+    }
+}
+
+/// Where the bulk endpoints are in the command/data/ack sequence
+enum Phase {
+    Command,
+    DataOut {
+        region: Region,
+        offset: usize,
+        len: usize,
+        data: Vec<u8>,
+    },
+    DataIn(Vec<u8>),
+    /// Waiting for the host to send the ack for a device-to-host command
+    AckOut,
+    /// Waiting for the host to read the ack for a host-to-device command
+    AckIn,
+    Stalled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Region {
+    Flash,
+    Sram,
+    Rom,
+}
+
+struct State {
+    target_id: TargetID,
+    flash: Vec<u8>,
+    sram: Vec<u8>,
+    rom: Vec<u8>,
+    exclusive: ExclusiveAccess,
+    xip: XipState,
+    phase: Phase,
+    status: CommandStatus,
+    pending_reboot: Option<RebootRequest>,
+    reboot: Option<RebootRequest>,
+    injected: Option<PicobootStatus>,
+    misreport: bool,
+    /// Flash address ranges erased and programmed since the log was last taken
+    erased: Vec<Range<u32>>,
+    written: Vec<Range<u32>>,
+    serial_number: Option<String>,
+    timing: Timing,
+    /// Time the flash has spent on the current transfer's work, waited out once it's done
+    busy: Duration,
+}
+
+impl State {
+    fn bulk_out(&mut self, buf: Vec<u8>) -> Result<usize, Error> {
+        match std::mem::replace(&mut self.phase, Phase::Command) {
+            Phase::Command => {
+                self.command(&buf);
+                Ok(buf.len())
+            }
+            Phase::DataOut {
+                region,
+                offset,
+                len,
+                mut data,
+            } => {
+                let accepted = buf.len().min(len - data.len());
+                data.extend_from_slice(&buf[..accepted]);
+                if data.len() < len {
+                    self.phase = Phase::DataOut {
+                        region,
+                        offset,
+                        len,
+                        data,
+                    };
+                } else {
+                    self.write(region, offset, &data);
+                    self.phase = Phase::AckIn;
+                }
+                Ok(accepted)
+            }
+            Phase::AckOut => {
+                self.complete();
+                Ok(buf.len())
+            }
+            Phase::Stalled => {
+                self.phase = Phase::Stalled;
+                Err(Error::Transfer(TransferError::Stall))
+            }
+            // the device isn't expecting anything from the host, so it NAKs forever
+            phase => {
+                self.phase = phase;
+                Err(Error::Timeout)
+            }
+        }
+    }
+
+    fn bulk_in(&mut self, len: usize) -> Result<Vec<u8>, Error> {
+        match std::mem::replace(&mut self.phase, Phase::Command) {
+            Phase::DataIn(mut data) => {
+                let rest = data.split_off(len.min(data.len()));
+                self.phase = if rest.is_empty() {
+                    Phase::AckOut
+                } else {
+                    Phase::DataIn(rest)
+                };
+                Ok(data)
+            }
+            Phase::AckIn => {
+                self.complete();
+                Ok(vec![])
+            }
+            Phase::Stalled => {
+                self.phase = Phase::Stalled;
+                Err(Error::Transfer(TransferError::Stall))
+            }
+            phase => {
+                self.phase = phase;
+                Err(Error::Timeout)
+            }
+        }
+    }
+
+    /// The ack has been exchanged, so the command is over
+    fn complete(&mut self) {
+        if let Some(reboot) = self.pending_reboot.take() {
+            self.reboot = Some(reboot);
+        }
+    }
+
+    fn command(&mut self, buf: &[u8]) {
+        let cmd: PicobootCmd = match bincode::deserialize(buf) {
+            Ok(cmd) if buf.len() == 32 => cmd,
+            // not a command packet at all, so there is no status to report
+            _ => {
+                self.phase = Phase::Stalled;
+                return;
+            }
+        };
+        if cmd.magic != PICOBOOT_MAGIC {
+            self.phase = Phase::Stalled;
+            return;
+        }
+
+        let misreport = std::mem::take(&mut self.misreport);
+        self.status = CommandStatus {
+            token: cmd.token.wrapping_sub(misreport as u32),
+            code: PicobootStatus::Ok,
+            cmd_id: cmd.cmd_id,
+        };
+        let result = match self.injected.take() {
+            Some(status) => Err(status),
+            None if self.reboot.is_some() => Err(PicobootStatus::Rebooting),
+            None => self.dispatch(&cmd),
+        };
+        match result {
+            Ok(phase) => self.phase = phase,
+            Err(status) => {
+                self.status.code = status;
+                self.phase = Phase::Stalled;
+            }
+        }
+    }
+
+    fn dispatch(&mut self, cmd: &PicobootCmd) -> Result<Phase, PicobootStatus> {
+        let id = PicobootCmdId::try_from(cmd.cmd_id).map_err(|_| PicobootStatus::UnknownCmd)?;
+        let cmd_size = self.cmd_size(id).ok_or(PicobootStatus::UnknownCmd)?;
+        if cmd.cmd_size != cmd_size {
+            return Err(PicobootStatus::InvalidCmdLength);
+        }
+
+        let transfer_len = cmd.transfer_len;
+        let args = cmd.args;
+        match id {
+            PicobootCmdId::Read | PicobootCmdId::Write => {
+                let range = PicobootRangeCmd::de(&args);
+                if transfer_len != range.size {
+                    return Err(PicobootStatus::InvalidTransferLength);
+                }
+            }
+            // the response is cut to fit
+            PicobootCmdId::GetInfo => {}
+            _ if transfer_len != 0 => return Err(PicobootStatus::InvalidTransferLength),
+            _ => {}
+        }
+
+        match id {
+            PicobootCmdId::ExclusiveAccess => {
+                self.exclusive = match args[0] {
+                    0 => ExclusiveAccess::NotExclusive,
+                    1 => ExclusiveAccess::Exclusive,
+                    2 => ExclusiveAccess::ExclusiveAndEject,
+                    _ => return Err(PicobootStatus::InvalidArg),
+                };
+                Ok(Phase::AckIn)
+            }
+            PicobootCmdId::Reboot => {
+                let reboot = PicobootRebootCmd::de(&args);
+                self.pending_reboot = Some(RebootRequest::Reboot {
+                    pc: reboot.pc,
+                    sp: reboot.sp,
+                    delay: reboot.delay,
+                });
+                Ok(Phase::AckIn)
+            }
+            PicobootCmdId::Reboot2 => {
+                let reboot = PicobootReboot2Cmd::de(&args);
+                self.check_reboot2(reboot.flags, reboot.p0, reboot.p1)?;
+                self.pending_reboot = Some(RebootRequest::Reboot2 {
+                    flags: reboot.flags,
+                    delay: reboot.delay,
+                    p0: reboot.p0,
+                    p1: reboot.p1,
+                });
+                Ok(Phase::AckIn)
+            }
+            PicobootCmdId::FlashErase => {
+                let range = PicobootRangeCmd::de(&args);
+                let (addr, size) = (range.addr, range.size);
+                let (region, offset) = self.region(addr, size)?;
+                if region != Region::Flash {
+                    return Err(PicobootStatus::InvalidAddress);
+                }
+                if addr % PICO_SECTOR_SIZE != 0 || size % PICO_SECTOR_SIZE != 0 {
+                    return Err(PicobootStatus::BadAlignment);
+                }
+                self.check_flash_writable()?;
+                self.flash[offset..offset + size as usize].fill(0xff);
+                self.erased.push(addr..addr + size);
+                self.erase_delay(offset, size as usize);
+                Ok(Phase::AckIn)
+            }
+            PicobootCmdId::Write => {
+                let range = PicobootRangeCmd::de(&args);
+                let (addr, size) = (range.addr, range.size);
+                let (region, offset) = self.region(addr, size)?;
+                match region {
+                    Region::Flash => {
+                        let page = PICO_PAGE_SIZE as u32;
+                        if addr % page != 0 || size % page != 0 {
+                            return Err(PicobootStatus::BadAlignment);
+                        }
+                        self.check_flash_writable()?;
+                    }
+                    Region::Sram => {}
+                    Region::Rom => return Err(PicobootStatus::InvalidAddress),
+                }
+                if size == 0 {
+                    return Ok(Phase::AckIn);
+                }
+                Ok(Phase::DataOut {
+                    region,
+                    offset,
+                    len: size as usize,
+                    data: Vec::with_capacity(size as usize),
+                })
+            }
+            PicobootCmdId::Read => {
+                let range = PicobootRangeCmd::de(&args);
+                let data = self.read(range.addr, range.size)?;
+                if data.is_empty() {
+                    return Ok(Phase::AckOut);
+                }
+                Ok(Phase::DataIn(data))
+            }
+            PicobootCmdId::ExitXip => {
+                self.xip = XipState::Serial;
+                Ok(Phase::AckIn)
+            }
+            PicobootCmdId::EnterCmdXip => {
+                self.xip = XipState::Xip;
+                Ok(Phase::AckIn)
+            }
+            // code is never run, but the bootrom only accepts a target in SRAM
+            PicobootCmdId::Exec | PicobootCmdId::VectorizeFlash => {
+                let addr = u32::from_le_bytes([args[0], args[1], args[2], args[3]]);
+                match self.region(addr, 0)? {
+                    (Region::Sram, offset) => {
+                        if id == PicobootCmdId::Exec {
+                            self.answer_jedec_id(offset & !1);
+                        }
+                        Ok(Phase::AckIn)
+                    }
+                    _ => Err(PicobootStatus::InvalidAddress),
+                }
+            }
+            PicobootCmdId::GetInfo => {
+                if args[0] != GET_INFO_SYS {
+                    return Err(PicobootStatus::UnknownCmd);
+                }
+                let flags = u32::from_le_bytes([args[4], args[5], args[6], args[7]]);
+                let mut data: Vec<u8> = self
+                    .sys_info(flags)
+                    .iter()
+                    .flat_map(|w| w.to_le_bytes())
+                    .collect();
+                data.resize(transfer_len as usize, 0);
+                if data.is_empty() {
+                    return Ok(Phase::AckOut);
+                }
+                Ok(Phase::DataIn(data))
+            }
+            PicobootCmdId::Unknown | PicobootCmdId::OtpRead | PicobootCmdId::OtpWrite => {
+                Err(PicobootStatus::UnknownCmd)
+            }
+        }
+    }
+
+    /// The argument length each command must declare, or None if this chip doesn't have it
+    fn cmd_size(&self, id: PicobootCmdId) -> Option<u8> {
+        let rp2040 = self.target_id == TargetID::Rp2040;
+        match id {
+            PicobootCmdId::ExclusiveAccess => Some(1),
+            PicobootCmdId::FlashErase | PicobootCmdId::Read | PicobootCmdId::Write => Some(8),
+            PicobootCmdId::ExitXip | PicobootCmdId::EnterCmdXip => Some(0),
+            PicobootCmdId::Reboot if rp2040 => Some(12),
+            PicobootCmdId::Exec | PicobootCmdId::VectorizeFlash if rp2040 => Some(4),
+            PicobootCmdId::Reboot2 | PicobootCmdId::GetInfo if !rp2040 => Some(16),
+            _ => None,
+        }
+    }
+
+    /// log2 of the flash size, if a real flash part could have it
+    fn flash_size_log2(&self) -> Option<u32> {
+        let size = self.flash.len();
+        (size.is_power_of_two() && size >= 64 * 1024).then(|| size.trailing_zeros())
+    }
+
+    /// Stand in for the JEDEC ID program at `offset` in SRAM, if that is what it is,
+    /// leaving the ID of a Winbond part of this size where the program would. The flash
+    /// only answers once XIP has been exited
+    fn answer_jedec_id(&mut self, offset: usize) {
+        let program = jedec_id_program();
+        if self.sram.get(offset..offset + program.len()) != Some(&program[..]) {
+            return;
+        }
+        let id = match self.flash_size_log2() {
+            Some(capacity) if self.xip == XipState::Serial => 0xef4000 | capacity,
+            _ => 0xffffff,
+        };
+        let result = offset + JEDEC_ID_RESULT_OFFSET as usize;
+        self.sram[result..result + 4].copy_from_slice(&id.to_le_bytes());
+    }
+
+    /// What get_sys_info fills in for `flags`: the count of words after it, the flags it
+    /// answered, then the answers. Only the flash size in FLASH_DEVINFO is known
+    fn sys_info(&self, flags: u32) -> Vec<u32> {
+        match self.flash_size_log2() {
+            Some(size) if flags & SYS_INFO_FLASH_DEV_INFO != 0 => {
+                // CS0 size in bits 11:8, as 4KiB shifted left by it
+                vec![2, SYS_INFO_FLASH_DEV_INFO, (size - 12) << 8]
+            }
+            _ => vec![1, 0],
+        }
+    }
+
+    /// The whole 16MiB XIP window can be read, with the flash repeating through it
+    /// as it does on the real part
+    fn read(&self, addr: u32, size: u32) -> Result<Vec<u8>, PicobootStatus> {
+        if addr >= PICO_FLASH_START && addr as u64 + size as u64 <= PICO_FLASH_END as u64 {
+            let offset = (addr - PICO_FLASH_START) as usize;
+            return Ok((offset..offset + size as usize)
+                .map(|i| self.flash[i % self.flash.len()])
+                .collect());
+        }
+        let (region, offset) = self.region(addr, size)?;
+        let mem = match region {
+            Region::Flash => &self.flash,
+            Region::Sram => &self.sram,
+            Region::Rom => &self.rom,
+        };
+        Ok(mem[offset..offset + size as usize].to_vec())
+    }
+
+    /// Find which memory `size` bytes at `addr` fall in, and the offset into it
+    fn region(&self, addr: u32, size: u32) -> Result<(Region, usize), PicobootStatus> {
+        let regions = [
+            (Region::Flash, PICO_FLASH_START, self.flash.len()),
+            (Region::Sram, SRAM_START, self.sram.len()),
+            (Region::Rom, 0, self.rom.len()),
+        ];
+        regions
+            .into_iter()
+            .find(|&(_, start, len)| {
+                addr >= start && addr as u64 + size as u64 <= start as u64 + len as u64
+            })
+            .map(|(region, start, _)| (region, (addr - start) as usize))
+            .ok_or(PicobootStatus::InvalidAddress)
+    }
+
+    /// The RP2040 can only program and erase flash once XIP has been exited.
+    /// RP2350 treats EXIT_XIP as a no-op and manages the flash mode itself
+    fn check_flash_writable(&self) -> Result<(), PicobootStatus> {
+        if self.target_id == TargetID::Rp2040 && self.xip != XipState::Serial {
+            return Err(PicobootStatus::InvalidState);
+        }
+        Ok(())
+    }
+
+    fn check_reboot2(&self, flags: u32, p0: u32, p1: u32) -> Result<(), PicobootStatus> {
+        if flags & REBOOT2_TO_ARM != 0 && flags & REBOOT2_TO_RISCV != 0 {
+            return Err(PicobootStatus::InvalidArg);
+        }
+        match flags & REBOOT2_TYPE_MASK {
+            REBOOT2_TYPE_NORMAL
+            | REBOOT2_TYPE_BOOTSEL
+            | REBOOT2_TYPE_FLASH_UPDATE
+            | REBOOT2_TYPE_PC_SP => Ok(()),
+            // p0 and p1 are the start and size of the image to boot
+            REBOOT2_TYPE_RAM_IMAGE => match self.region(p0, p1)? {
+                (Region::Sram, _) => Ok(()),
+                _ => Err(PicobootStatus::InvalidAddress),
+            },
+            _ => Err(PicobootStatus::InvalidArg),
+        }
+    }
+
+    /// Flash programming can only clear bits, so unerased bytes read back as the AND
+    /// of old and new data, exactly as on the real part
+    fn write(&mut self, region: Region, offset: usize, data: &[u8]) {
+        match region {
+            Region::Flash => {
+                for (cell, byte) in self.flash[offset..].iter_mut().zip(data) {
+                    *cell &= byte;
+                }
+                let addr = PICO_FLASH_START + offset as u32;
+                self.written.push(addr..addr + data.len() as u32);
+                let pages = data.len().div_ceil(PICO_PAGE_SIZE) as u32;
+                self.busy += self.timing.page_program * pages;
+            }
+            Region::Sram => self.sram[offset..offset + data.len()].copy_from_slice(data),
+            Region::Rom => unreachable!("writes to ROM are rejected"),
+        }
+    }
+
+    /// Add the time an erase of `size` bytes at `offset` takes, using block erases
+    /// where aligned
+    fn erase_delay(&mut self, offset: usize, size: usize) {
+        let sector = PICO_SECTOR_SIZE as usize;
+        let (mut at, mut delay) = (offset, Duration::ZERO);
+        while at < offset + size {
+            if at.is_multiple_of(FLASH_BLOCK_SIZE) && offset + size - at >= FLASH_BLOCK_SIZE {
+                delay += self.timing.block_erase;
+                at += FLASH_BLOCK_SIZE;
+            } else {
+                delay += self.timing.sector_erase;
+                at += sector;
+            }
+        }
+        self.busy += delay;
+    }
+}
@@ -9,30 +9,228 @@
 // section 2.8.5 for details on PICOBOOT interface
 
 use crate::picoboot::cmd::*;
-use crate::TargetID;
+use crate::{DeviceSelector, Error, TargetID};
 use async_io::{block_on, Timer};
 use bincode;
 use futures_lite::FutureExt;
 use nusb::{
     transfer::{
-        ControlIn, ControlOut, ControlType, Direction, EndpointType, Recipient, RequestBuffer,
+        ControlIn, ControlOut, ControlType, Direction, EndpointType, Queue, Recipient,
+        RequestBuffer,
     },
     Device, DeviceInfo,
 };
-use std::{io, time::Duration};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::time::{Duration, Instant};
 
 const PICOBOOT_VID: u16 = 0x2E8A;
 const PICOBOOT_PID_RP2040: u16 = 0x0003;
 const PICOBOOT_PID_RP2350: u16 = 0x000f;
 
 const USB_TIMEOUT: Duration = Duration::from_millis(5000);
+// long enough for a whole-chip erase to finish
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+const STATUS_POLL_INTERVAL: Duration = Duration::from_millis(10);
+// large bulk transfers are split into chunks of this size, several of which are
+// kept queued on the endpoint so the bus never sits idle between them
+const BULK_CHUNK_SIZE: usize = 16 * 1024;
+const BULK_QUEUE_DEPTH: usize = 4;
+
+// room for every word get_sys_info can return
+const SYS_INFO_LEN: u32 = 64;
+
+const PICOBOOT_REQUEST_INTERFACE_RESET: u8 = 0b01000001;
+const PICOBOOT_REQUEST_GET_COMMAND_STATUS: u8 = 0b01000010;
+
+/// The USB operations the PICOBOOT command layer needs from a device.
+///
+/// [`NusbTransport`] talks to real hardware. Other implementations let the
+/// command layer and [`crate::PicoTool`] run without a device attached.
+///
+/// The transfers are futures that need no particular async runtime. A transfer
+/// whose future is dropped before it finishes must not disturb the next one
+pub trait PicobootTransport: Send {
+    /// Read up to `len` bytes from the bulk IN endpoint
+    fn bulk_in(&mut self, len: usize) -> impl Future<Output = Result<Vec<u8>, Error>> + Send;
+
+    /// Write `buf` to the bulk OUT endpoint, returning how many bytes were sent
+    fn bulk_out(&mut self, buf: Vec<u8>) -> impl Future<Output = Result<usize, Error>> + Send;
+
+    /// Send the vendor INTERFACE_RESET control request
+    fn reset_interface(&mut self) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Send the vendor GET_COMMAND_STATUS control request and return the raw response
+    fn command_status(&mut self) -> impl Future<Output = Result<Vec<u8>, Error>> + Send;
+
+    /// The chip on the other end of this transport
+    fn target_id(&self) -> TargetID;
+
+    /// The device's USB serial number, which the bootrom derives from the chip's unique ID
+    fn serial_number(&self) -> Option<String> {
+        None
+    }
+}
 
-struct ConnectionContext {
+/// Cancel anything still queued after a failed, short or abandoned transfer, and wait
+/// for it to go. A macro because nusb doesn't export the trait both kinds of queue are
+/// generic over
+macro_rules! drain {
+    ($queue:expr) => {
+        if $queue.pending() > 0 {
+            $queue.cancel_all();
+            while $queue.pending() > 0 {
+                let _ = $queue.next_complete().await;
+            }
+        }
+    };
+}
+
+/// Give up on `fut` with [`Error::Timeout`] if it hasn't finished after `timeout`
+async fn with_timeout<T>(
+    timeout: Duration,
+    fut: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    fut.or(async {
+        Timer::after(timeout).await;
+        Err(Error::Timeout)
+    })
+    .await
+}
+
+/// PICOBOOT transport backed by a real USB device, via nusb
+pub struct NusbTransport {
     target_id: TargetID,
+    serial_number: Option<String>,
     device: Device,
     interface: nusb::Interface,
-    endpoint_out_addr: u8,
-    endpoint_in_addr: u8,
+    in_queue: Queue<RequestBuffer>,
+    out_queue: Queue<Vec<u8>>,
+}
+
+impl NusbTransport {
+    /// Open the only RP device in BOOTSEL mode
+    pub fn open() -> Result<Self, Error> {
+        Self::open_selected(&DeviceSelector::default())
+    }
+
+    /// Open the only RP device in BOOTSEL mode that `selector` matches
+    pub fn open_selected(selector: &DeviceSelector) -> Result<Self, Error> {
+        let devices = picoboot_devices()?;
+        Self::open_device(selector.select(&devices)?)
+    }
+
+    /// Open `device`, which must be an RP device in BOOTSEL mode. Each transport owns
+    /// its device, so several can be used at once from different threads
+    pub fn open_device(device: &DeviceInfo) -> Result<Self, Error> {
+        open_device(device)
+    }
+}
+
+impl PicobootTransport for NusbTransport {
+    async fn bulk_in(&mut self, len: usize) -> Result<Vec<u8>, Error> {
+        // left over from a transfer whose future was dropped part way
+        drain!(self.in_queue);
+        let queue = &mut self.in_queue;
+        let fut = async {
+            let mut buf = Vec::with_capacity(len);
+            let mut requested = VecDeque::new();
+            let mut unsubmitted = len.max(1);
+            loop {
+                while unsubmitted > 0 && queue.pending() < BULK_QUEUE_DEPTH {
+                    let chunk = unsubmitted.min(BULK_CHUNK_SIZE);
+                    queue.submit(RequestBuffer::new(chunk));
+                    requested.push_back(chunk);
+                    unsubmitted -= chunk;
+                }
+                if queue.pending() == 0 {
+                    return Ok(buf);
+                }
+
+                let comp = queue.next_complete().await;
+                comp.status?;
+                let short = comp.data.len() < requested.pop_front().unwrap_or(0);
+                buf.extend_from_slice(&comp.data);
+                // a short packet ends the transfer, so nothing more is coming
+                if short {
+                    return Ok(buf);
+                }
+            }
+        };
+
+        let result = with_timeout(USB_TIMEOUT, fut).await;
+        drain!(self.in_queue);
+        result
+    }
+
+    async fn bulk_out(&mut self, buf: Vec<u8>) -> Result<usize, Error> {
+        drain!(self.out_queue);
+        let queue = &mut self.out_queue;
+        let fut = async {
+            let mut chunks = buf.chunks(BULK_CHUNK_SIZE);
+            // an empty buffer still goes out, as a zero length packet
+            let mut unsubmitted = if buf.is_empty() { vec![vec![]] } else { vec![] };
+            let mut sent = 0;
+            loop {
+                while queue.pending() < BULK_QUEUE_DEPTH {
+                    match unsubmitted
+                        .pop()
+                        .or_else(|| chunks.next().map(<[u8]>::to_vec))
+                    {
+                        Some(chunk) => queue.submit(chunk),
+                        None => break,
+                    }
+                }
+                if queue.pending() == 0 {
+                    return Ok(sent);
+                }
+
+                let comp = queue.next_complete().await;
+                comp.status?;
+                sent += comp.data.actual_length();
+            }
+        };
+
+        let result = with_timeout(USB_TIMEOUT, fut).await;
+        drain!(self.out_queue);
+        result
+    }
+
+    async fn reset_interface(&mut self) -> Result<(), Error> {
+        let transfer = self.device.control_out(ControlOut {
+            control_type: ControlType::Vendor,
+            recipient: Recipient::Interface,
+            request: PICOBOOT_REQUEST_INTERFACE_RESET,
+            value: 0,
+            index: self.interface.interface_number() as u16,
+            data: &[],
+        });
+        with_timeout(USB_TIMEOUT, async {
+            transfer.await.into_result()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn command_status(&mut self) -> Result<Vec<u8>, Error> {
+        let transfer = self.interface.control_in(ControlIn {
+            control_type: ControlType::Vendor,
+            recipient: Recipient::Interface,
+            request: PICOBOOT_REQUEST_GET_COMMAND_STATUS,
+            value: 0,
+            index: self.interface.interface_number() as u16,
+            length: 16,
+        });
+        with_timeout(USB_TIMEOUT, async { Ok(transfer.await.into_result()?) }).await
+    }
+
+    fn target_id(&self) -> TargetID {
+        self.target_id
+    }
+
+    fn serial_number(&self) -> Option<String> {
+        self.serial_number.clone()
+    }
 }
 
 fn is_picoboot_device(device: &nusb::DeviceInfo) -> bool {
@@ -42,7 +240,7 @@ fn is_picoboot_device(device: &nusb::DeviceInfo) -> bool {
     )
 }
 
-fn picoboot_device_type(device: &nusb::DeviceInfo) -> Option<TargetID> {
+pub(crate) fn picoboot_device_type(device: &nusb::DeviceInfo) -> Option<TargetID> {
     match (device.vendor_id(), device.product_id()) {
         (PICOBOOT_VID, PICOBOOT_PID_RP2040) => Some(TargetID::Rp2040),
         (PICOBOOT_VID, PICOBOOT_PID_RP2350) => Some(TargetID::Rp2350),
@@ -50,31 +248,20 @@ fn picoboot_device_type(device: &nusb::DeviceInfo) -> Option<TargetID> {
     }
 }
 
-fn open_device() -> Option<ConnectionContext> {
-    let devices: Vec<DeviceInfo> = nusb::list_devices()
-        .unwrap()
+/// Every RP device in BOOTSEL mode
+pub(crate) fn picoboot_devices() -> Result<Vec<DeviceInfo>, Error> {
+    Ok(nusb::list_devices()
+        .map_err(Error::Usb)?
         .filter(is_picoboot_device)
-        .collect();
-    for device in &devices {
-        println!(
-            "Found an {:?} in bootsel mode",
-            picoboot_device_type(device).unwrap()
-        );
-    }
-    if devices.is_empty() {
-        println!("No devices in bootsel mode found. Exiting!");
-        return None;
-    }
-    if devices.len() > 1 {
-        println!("Found more than one device. Using the first one found");
-    }
+        .collect())
+}
 
-    let device = devices.first().unwrap();
-    let targetid = picoboot_device_type(device).unwrap();
+fn open_device(device: &DeviceInfo) -> Result<NusbTransport, Error> {
+    let targetid = picoboot_device_type(device).ok_or(Error::NoDevice)?;
     let mut endpoint_out_addr = None;
     let mut endpoint_in_addr = None;
     let mut endpoint_interfacenum = None;
-    let device_handle = device.open().unwrap();
+    let device_handle = device.open().map_err(Error::Usb)?;
     let mut configs = device_handle.configurations();
     if let Some(config) = configs.next() {
         for interface in config.interfaces() {
@@ -103,223 +290,411 @@ fn open_device() -> Option<ConnectionContext> {
         }
     }
 
-    let interface = if let Ok(interface) =
-        device_handle.claim_interface(endpoint_interfacenum.expect("No interface found"))
-    {
+    let endpoint_interfacenum = endpoint_interfacenum.ok_or(Error::NoInterface)?;
+    let interface = if let Ok(interface) = device_handle.claim_interface(endpoint_interfacenum) {
         interface
     } else {
         // maybe device is attached to OS driver? try to detach too
         device_handle
-            .detach_and_claim_interface(endpoint_interfacenum.unwrap())
-            .expect("could not detach and claim interface")
+            .detach_and_claim_interface(endpoint_interfacenum)
+            .map_err(Error::Usb)?
     };
 
-    if endpoint_in_addr.is_some() && endpoint_out_addr.is_some() {
-        let endpoint_out_addr = endpoint_out_addr.unwrap();
-        let endpoint_in_addr = endpoint_in_addr.unwrap();
-        Some(ConnectionContext {
+    match (endpoint_in_addr, endpoint_out_addr) {
+        (Some(endpoint_in_addr), Some(endpoint_out_addr)) => Ok(NusbTransport {
             target_id: targetid,
+            serial_number: device.serial_number().map(String::from),
             device: device_handle.clone(),
+            in_queue: interface.bulk_in_queue(endpoint_in_addr),
+            out_queue: interface.bulk_out_queue(endpoint_out_addr),
             interface,
-            endpoint_out_addr,
-            endpoint_in_addr,
-        })
-    } else {
-        None
+        }),
+        _ => Err(Error::NoInterface),
     }
 }
 
-// #[derive(Debug)]
-#[allow(dead_code)]
-pub struct PicobootConnection {
-    ctx: ConnectionContext,
+/// A PICOBOOT command session with one device. Its futures run under any async
+/// runtime, and every transfer is given up on after a timeout
+pub struct AsyncPicobootConnection<T: PicobootTransport = NusbTransport> {
+    transport: T,
     cmd_token: u32,
     target_id: Option<TargetID>,
+    // set while a command is being exchanged, so that one whose future was dropped
+    // part way through can be cleared off the device before the next
+    in_flight: bool,
 }
 
-impl PicobootConnection {
-    pub fn new() -> Option<Self> {
-        let d = open_device();
-        if d.is_none() {
-            None
-        } else {
-            let d = d.unwrap();
-            let target_id = d.target_id;
-
-            Some(PicobootConnection {
-                ctx: d,
-                cmd_token: 1,
-                target_id: Some(target_id),
-            })
-        }
+impl AsyncPicobootConnection<NusbTransport> {
+    pub fn new() -> Result<Self, Error> {
+        Ok(Self::with_transport(NusbTransport::open()?))
     }
+}
 
-    fn bulk_read(&mut self, buf_size: usize, check: bool) -> io::Result<Vec<u8>> {
-        let mut queue = self.ctx.interface.bulk_in_queue(self.ctx.endpoint_in_addr);
+impl<T: PicobootTransport> AsyncPicobootConnection<T> {
+    pub fn with_transport(transport: T) -> Self {
+        let target_id = transport.target_id();
 
-        queue.submit(RequestBuffer::new(buf_size));
-        let result = block_on(queue.next_complete());
-        let buf = result.into_result().unwrap();
+        AsyncPicobootConnection {
+            transport,
+            cmd_token: 1,
+            target_id: Some(target_id),
+            in_flight: false,
+        }
+    }
+
+    async fn bulk_read(&mut self, buf_size: usize, check: bool) -> Result<Vec<u8>, Error> {
+        let buf = self.transport.bulk_in(buf_size).await?;
         let len = buf.len();
 
         if check && len != buf_size {
-            panic!("read mismatch {} != {}", len, buf_size)
+            return Err(Error::ShortTransfer {
+                expected: buf_size,
+                actual: len,
+            });
         }
 
         Ok(buf)
     }
 
-    fn bulk_write(&mut self, buf: Vec<u8>, check: bool) -> io::Result<()> {
-        let fut = async {
-            let comp = self
-                .ctx
-                .interface
-                .bulk_out(self.ctx.endpoint_out_addr, buf.to_vec())
-                .await;
-            comp.status.map_err(io::Error::other)?;
-
-            let len = comp.data.actual_length();
-            if check && len != buf.len() {
-                panic!("write mismatch {} != {}", len, buf.len())
-            }
-            Ok(())
-        };
+    async fn bulk_write(&mut self, buf: Vec<u8>, check: bool) -> Result<(), Error> {
+        let expected = buf.len();
+        let len = self.transport.bulk_out(buf).await?;
+        if check && len != expected {
+            return Err(Error::ShortTransfer {
+                expected,
+                actual: len,
+            });
+        }
+        Ok(())
+    }
 
-        block_on(fut.or(async {
-            Timer::after(USB_TIMEOUT).await;
-            Err(std::io::ErrorKind::TimedOut.into())
-        }))
+    /// Run one command. Its future can be dropped part way through, as the next
+    /// command then resets the interface first
+    async fn cmd(&mut self, cmd: PicobootCmd, buf: Vec<u8>) -> Result<Vec<u8>, Error> {
+        if self.in_flight {
+            // the last command was cancelled, and the device may still be waiting for
+            // the rest of it
+            self.reset_interface().await?;
+            self.in_flight = false;
+        }
+        self.in_flight = true;
+        let res = self.exchange(cmd, buf).await;
+        self.in_flight = false;
+        res
     }
 
-    fn cmd(&mut self, mut cmd: PicobootCmd, buf: Vec<u8>) -> io::Result<Vec<u8>> {
+    /// Send `cmd` and run its data and ack phases
+    async fn exchange(&mut self, mut cmd: PicobootCmd, buf: Vec<u8>) -> Result<Vec<u8>, Error> {
         cmd.token = self.cmd_token;
         self.cmd_token += 1;
         let cmd = cmd;
 
-        // write command
-        let cmdu8 = bincode::serialize(&cmd).expect("failed to serialize cmd");
-        self.bulk_write(cmdu8, true).expect("failed to write cmd");
-        let _stat = self.get_command_status();
-
         // if we're reading or writing a buffer
-        let l = cmd.transfer_len.try_into().unwrap();
-        let mut res: Option<Vec<_>> = Some(vec![]);
-        if l != 0 {
-            if (cmd.cmd_id & 0x80) != 0 {
-                res = Some(self.bulk_read(l, true).unwrap());
+        let l = cmd.transfer_len as usize;
+
+        // write command
+        let cmdu8 = bincode::serialize(&cmd).expect("PicobootCmd is always serializable");
+        if let Err(e) = self.bulk_write(cmdu8, true).await {
+            return Err(self.status_error(&cmd, e).await);
+        }
+        // commands without a data phase run to completion before the ack, which
+        // may take longer than a transfer timeout, so wait for them here. Data
+        // commands go straight on: a rejected one stalls the data phase, and the
+        // ack isn't given until the command has finished.
+        let mut res = vec![];
+        if l == 0 {
+            self.check_status(&cmd, true).await?;
+        } else {
+            let transfer = if (cmd.cmd_id & 0x80) != 0 {
+                self.bulk_read(l, true).await.map(|buf| res = buf)
             } else {
-                self.bulk_write(buf, true).unwrap()
+                self.bulk_write(buf, true).await
+            };
+            if let Err(e) = transfer {
+                return Err(self.status_error(&cmd, e).await);
             }
-            let _stat = self.get_command_status();
         }
 
         // do ack
-        if (cmd.cmd_id & 0x80) != 0 {
-            self.bulk_write(vec![0], false).unwrap();
+        let ack = if (cmd.cmd_id & 0x80) != 0 {
+            self.bulk_write(vec![0], false).await
         } else {
-            self.bulk_read(1, false).unwrap();
+            self.bulk_read(1, false).await.map(|_| ())
+        };
+        if let Err(e) = ack {
+            return Err(self.status_error(&cmd, e).await);
         }
 
-        Ok(res.unwrap())
+        Ok(res)
+    }
+
+    /// Check the device's view of `cmd`, optionally polling until it has finished running
+    async fn check_status(&mut self, cmd: &PicobootCmd, wait: bool) -> Result<(), Error> {
+        let (token, cmd_id) = (cmd.token, cmd.cmd_id);
+        let deadline = Instant::now() + COMMAND_TIMEOUT;
+        loop {
+            let stat = self.get_command_status().await?;
+            if stat.token != token || stat.cmd_id != cmd_id {
+                return Err(Error::StatusMismatch {
+                    token,
+                    cmd_id,
+                    status_token: stat.token,
+                    status_cmd_id: stat.cmd_id,
+                });
+            }
+            let status =
+                PicobootStatus::try_from(stat.status_code).unwrap_or(PicobootStatus::UnknownError);
+            if status != PicobootStatus::Ok {
+                return Err(Error::Status {
+                    status,
+                    cmd_id,
+                    token,
+                });
+            }
+            if !wait || stat.in_progress == 0 {
+                return Ok(());
+            }
+            if Instant::now() > deadline {
+                return Err(Error::Timeout);
+            }
+            Timer::after(STATUS_POLL_INTERVAL).await;
+        }
+    }
+
+    /// A failed transfer usually means the device stalled the endpoint after rejecting
+    /// `cmd`. Prefer the device's reason over the bare transfer error when it has one.
+    async fn status_error(&mut self, cmd: &PicobootCmd, err: Error) -> Error {
+        match self.check_status(cmd, false).await {
+            Err(status @ Error::Status { .. }) => status,
+            _ => err,
+        }
     }
 
     #[allow(dead_code)]
-    pub fn access_not_exclusive(&mut self) -> io::Result<()> {
-        self.set_exclusive_access(0)
+    pub async fn access_not_exclusive(&mut self) -> Result<(), Error> {
+        self.set_exclusive_access(0).await
     }
 
     #[allow(dead_code)]
-    pub fn access_exclusive(&mut self) -> io::Result<()> {
-        self.set_exclusive_access(1)
+    pub async fn access_exclusive(&mut self) -> Result<(), Error> {
+        self.set_exclusive_access(1).await
     }
 
     #[allow(dead_code)]
-    pub fn access_exclusive_eject(&mut self) -> io::Result<()> {
-        self.set_exclusive_access(2)
+    pub async fn access_exclusive_eject(&mut self) -> Result<(), Error> {
+        self.set_exclusive_access(2).await
     }
 
-    fn set_exclusive_access(&mut self, exclusive: u8) -> io::Result<()> {
+    async fn set_exclusive_access(&mut self, exclusive: u8) -> Result<(), Error> {
         let mut args = [0; 16];
         args[0] = exclusive;
         let cmd = PicobootCmd::new(PicobootCmdId::ExclusiveAccess, 1, 0, args);
-        self.cmd(cmd, vec![]).map(|_| ())
+        self.cmd(cmd, vec![]).await.map(|_| ())
     }
 
-    pub fn reboot(&mut self, pc: u32, sp: u32, delay: u32) -> io::Result<()> {
+    pub async fn reboot(&mut self, pc: u32, sp: u32, delay: u32) -> Result<(), Error> {
         let args = PicobootRebootCmd::ser(pc, sp, delay);
         let cmd = PicobootCmd::new(PicobootCmdId::Reboot, 12, 0, args);
-        self.cmd(cmd, vec![]).map(|_| ())
+        self.cmd(cmd, vec![]).await.map(|_| ())
+    }
+
+    pub async fn reboot2_normal(&mut self, delay: u32) -> Result<(), Error> {
+        self.reboot2(&Reboot2::normal().delay(delay)).await
+    }
+
+    /// Boot the image of `size` bytes already loaded at `start` in SRAM
+    pub async fn reboot2_ram_image(
+        &mut self,
+        start: u32,
+        size: u32,
+        delay: u32,
+    ) -> Result<(), Error> {
+        self.reboot2(&Reboot2::ram_image(start, size).delay(delay))
+            .await
     }
 
-    pub fn reboot2_normal(&mut self, delay: u32) -> io::Result<()> {
-        let flags: u32 = 0x0; // Normal boot
-        let args = PicobootReboot2Cmd::ser(flags, delay, 0, 0);
+    /// RP2350 only. Fails with [`Error::InvalidReboot`] without sending anything if the
+    /// options don't go together
+    pub async fn reboot2(&mut self, reboot: &Reboot2) -> Result<(), Error> {
+        let args = reboot.args()?;
         let cmd = PicobootCmd::new(PicobootCmdId::Reboot2, 0x10, 0, args);
-        self.cmd(cmd, vec![]).map(|_| ())
+        self.cmd(cmd, vec![]).await.map(|_| ())
     }
 
-    pub fn flash_erase(&mut self, addr: u32, size: u32) -> io::Result<()> {
+    /// RP2350 only: the words the bootrom's get_sys_info fills in for `flags`, starting
+    /// with a count and the flags it could answer
+    pub async fn get_sys_info(&mut self, flags: u32) -> Result<Vec<u32>, Error> {
+        let mut args = [0; 16];
+        args[0] = GET_INFO_SYS;
+        args[4..8].copy_from_slice(&flags.to_le_bytes());
+        let cmd = PicobootCmd::new(PicobootCmdId::GetInfo, 16, SYS_INFO_LEN, args);
+        let buf = self.cmd(cmd, vec![]).await?;
+        Ok(buf
+            .chunks_exact(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect())
+    }
+
+    /// RP2040 only: call the Thumb function at `addr` in SRAM, waiting for it to return
+    pub async fn exec(&mut self, addr: u32) -> Result<(), Error> {
+        let mut args = [0; 16];
+        args[..4].copy_from_slice(&addr.to_le_bytes());
+        let cmd = PicobootCmd::new(PicobootCmdId::Exec, 4, 0, args);
+        self.cmd(cmd, vec![]).await.map(|_| ())
+    }
+
+    pub async fn flash_erase(&mut self, addr: u32, size: u32) -> Result<(), Error> {
         let args = PicobootRangeCmd::ser(addr, size);
         let cmd = PicobootCmd::new(PicobootCmdId::FlashErase, 8, 0, args);
-        self.cmd(cmd, vec![]).map(|_| ())
+        self.cmd(cmd, vec![]).await.map(|_| ())
     }
 
-    pub fn flash_write(&mut self, addr: u32, buf: Vec<u8>) -> io::Result<()> {
+    pub async fn flash_write(&mut self, addr: u32, buf: Vec<u8>) -> Result<(), Error> {
         let args = PicobootRangeCmd::ser(addr, buf.len() as u32);
         let cmd = PicobootCmd::new(PicobootCmdId::Write, 8, buf.len() as u32, args);
-        self.cmd(cmd, buf).map(|_| ())
+        self.cmd(cmd, buf).await.map(|_| ())
     }
 
-    pub fn flash_read(&mut self, addr: u32, size: u32) -> io::Result<Vec<u8>> {
+    pub async fn flash_read(&mut self, addr: u32, size: u32) -> Result<Vec<u8>, Error> {
         let args = PicobootRangeCmd::ser(addr, size);
         let cmd = PicobootCmd::new(PicobootCmdId::Read, 8, size, args);
-        self.cmd(cmd, vec![])
+        self.cmd(cmd, vec![]).await
     }
 
     #[allow(dead_code)]
-    pub fn enter_xip(&mut self) -> io::Result<()> {
+    pub async fn enter_xip(&mut self) -> Result<(), Error> {
         let args = [0; 16];
         let cmd = PicobootCmd::new(PicobootCmdId::EnterCmdXip, 0, 0, args);
-        self.cmd(cmd, vec![]).map(|_| ())
+        self.cmd(cmd, vec![]).await.map(|_| ())
     }
 
-    pub fn exit_xip(&mut self) -> io::Result<()> {
+    pub async fn exit_xip(&mut self) -> Result<(), Error> {
         let args = [0; 16];
         let cmd = PicobootCmd::new(PicobootCmdId::ExitXip, 0, 0, args);
-        self.cmd(cmd, vec![]).map(|_| ())
+        self.cmd(cmd, vec![]).await.map(|_| ())
     }
 
-    pub fn reset_interface(&mut self) {
-        let result = block_on(self.ctx.device.control_out(ControlOut {
-            control_type: ControlType::Vendor,
-            recipient: Recipient::Interface,
-            request: 0b01000001,
-            value: 0,
-            index: self.ctx.interface.interface_number() as u16,
-            data: &[],
-        }));
-        let _ = result.into_result().expect("failed to reset");
+    pub async fn reset_interface(&mut self) -> Result<(), Error> {
+        self.transport.reset_interface().await
     }
 
-    fn get_command_status(&mut self) -> PicobootStatusCmd {
-        let result = block_on(self.ctx.interface.control_in(ControlIn {
-            control_type: ControlType::Vendor,
-            recipient: Recipient::Interface,
-            request: 0b01000010,
-            value: 0,
-            index: self.ctx.interface.interface_number() as u16,
-            length: 16,
-        }));
-
-        let buf = result.into_result().expect("failed to get command status");
+    async fn get_command_status(&mut self) -> Result<PicobootStatusCmd, Error> {
+        let buf = self.transport.command_status().await?;
+        if buf.len() != 16 {
+            return Err(Error::ShortTransfer {
+                expected: 16,
+                actual: buf.len(),
+            });
+        }
 
         let buf: PicobootStatusCmd =
-            bincode::deserialize(&buf).expect("failed to parse command status buffer");
+            bincode::deserialize(&buf).expect("status buffer length was checked above");
 
-        buf
+        Ok(buf)
     }
 
     pub fn get_device_type(&self) -> Option<TargetID> {
         self.target_id
     }
+
+    pub fn serial_number(&self) -> Option<String> {
+        self.transport.serial_number()
+    }
+}
+
+/// Blocking wrapper around [`AsyncPicobootConnection`], running each command to
+/// completion on the calling thread
+pub struct PicobootConnection<T: PicobootTransport = NusbTransport> {
+    inner: AsyncPicobootConnection<T>,
+}
+
+impl PicobootConnection<NusbTransport> {
+    pub fn new() -> Result<Self, Error> {
+        Ok(Self::with_transport(NusbTransport::open()?))
+    }
+}
+
+impl<T: PicobootTransport> PicobootConnection<T> {
+    pub fn with_transport(transport: T) -> Self {
+        PicobootConnection {
+            inner: AsyncPicobootConnection::with_transport(transport),
+        }
+    }
+
+    /// The async connection this wraps
+    pub fn into_async(self) -> AsyncPicobootConnection<T> {
+        self.inner
+    }
+
+    pub fn access_not_exclusive(&mut self) -> Result<(), Error> {
+        block_on(self.inner.access_not_exclusive())
+    }
+
+    pub fn access_exclusive(&mut self) -> Result<(), Error> {
+        block_on(self.inner.access_exclusive())
+    }
+
+    pub fn access_exclusive_eject(&mut self) -> Result<(), Error> {
+        block_on(self.inner.access_exclusive_eject())
+    }
+
+    pub fn reboot(&mut self, pc: u32, sp: u32, delay: u32) -> Result<(), Error> {
+        block_on(self.inner.reboot(pc, sp, delay))
+    }
+
+    pub fn reboot2_normal(&mut self, delay: u32) -> Result<(), Error> {
+        block_on(self.inner.reboot2_normal(delay))
+    }
+
+    /// Boot the image of `size` bytes already loaded at `start` in SRAM
+    pub fn reboot2_ram_image(&mut self, start: u32, size: u32, delay: u32) -> Result<(), Error> {
+        block_on(self.inner.reboot2_ram_image(start, size, delay))
+    }
+
+    /// See [`AsyncPicobootConnection::reboot2`]
+    pub fn reboot2(&mut self, reboot: &Reboot2) -> Result<(), Error> {
+        block_on(self.inner.reboot2(reboot))
+    }
+
+    /// RP2350 only: the words the bootrom's get_sys_info fills in for `flags`
+    pub fn get_sys_info(&mut self, flags: u32) -> Result<Vec<u32>, Error> {
+        block_on(self.inner.get_sys_info(flags))
+    }
+
+    /// RP2040 only: call the Thumb function at `addr` in SRAM, waiting for it to return
+    pub fn exec(&mut self, addr: u32) -> Result<(), Error> {
+        block_on(self.inner.exec(addr))
+    }
+
+    pub fn flash_erase(&mut self, addr: u32, size: u32) -> Result<(), Error> {
+        block_on(self.inner.flash_erase(addr, size))
+    }
+
+    pub fn flash_write(&mut self, addr: u32, buf: Vec<u8>) -> Result<(), Error> {
+        block_on(self.inner.flash_write(addr, buf))
+    }
+
+    pub fn flash_read(&mut self, addr: u32, size: u32) -> Result<Vec<u8>, Error> {
+        block_on(self.inner.flash_read(addr, size))
+    }
+
+    pub fn enter_xip(&mut self) -> Result<(), Error> {
+        block_on(self.inner.enter_xip())
+    }
+
+    pub fn exit_xip(&mut self) -> Result<(), Error> {
+        block_on(self.inner.exit_xip())
+    }
+
+    pub fn reset_interface(&mut self) -> Result<(), Error> {
+        block_on(self.inner.reset_interface())
+    }
+
+    pub fn get_device_type(&self) -> Option<TargetID> {
+        self.inner.get_device_type()
+    }
+
+    pub fn serial_number(&self) -> Option<String> {
+        self.inner.serial_number()
+    }
 }
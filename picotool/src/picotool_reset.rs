@@ -1,53 +1,223 @@
+use crate::device::{port_path, wait_for_device, DeviceMode, RpDevice};
+use crate::picoboot::cmd::{BOOTSEL_DISABLE_MSD, BOOTSEL_DISABLE_PICOBOOT};
+use crate::picoboot::usb::picoboot_devices;
+use crate::{DeviceSelector, Error};
 use async_io::block_on;
 use nusb::{
     transfer::{ControlOut, ControlType, Recipient},
-    DeviceInfo,
+    Device, DeviceInfo,
 };
-const RP_VID: u16 = 0x2E8A;
+use std::time::Duration;
+pub(crate) const RP_VID: u16 = 0x2E8A;
 
+// see pico-sdk src/rp2_common/pico_stdio_usb/reset_interface.c
 const RESET_REQUEST_BOOTSEL: u8 = 0x01;
-// const RESET_REQUEST_FLASH: u8 = 0x02;
+const RESET_REQUEST_FLASH: u8 = 0x02;
+// a BOOTSEL request's wValue holds the interface disable mask in its low 7 bits,
+// and the activity LED pin above this flag
+const RESET_BOOTSEL_GPIO_SPECIFIED: u16 = 0x100;
+const RESET_BOOTSEL_GPIO_SHIFT: u16 = 9;
+// as many GPIOs as either chip has
+const MAX_GPIO: u8 = 48;
 
-pub fn reset_usb_device() {
-    let devices: Vec<DeviceInfo> = nusb::list_devices()
-        .unwrap()
-        .filter(|d| d.vendor_id() == RP_VID)
-        .collect();
+/// What the reset interface asks the firmware to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetMode {
+    /// Reboot into BOOTSEL mode
+    Bootsel {
+        disable_msd: bool,
+        disable_picoboot: bool,
+        /// GPIO for the bootloader to drive high while it is busy
+        activity_led: Option<u8>,
+    },
+    /// Reboot back into the application in flash
+    Application,
+}
 
-    if devices.is_empty() {
-        println!("No USB devices found with a Raspberry Pi VendorID");
-        return;
-    }
+impl ResetMode {
+    /// BOOTSEL mode with every interface and no activity LED
+    pub const BOOTSEL: ResetMode = ResetMode::Bootsel {
+        disable_msd: false,
+        disable_picoboot: false,
+        activity_led: None,
+    };
 
-    if devices.len() > 1 {
-        println!("Found more than one device. Using the first one found");
+    fn request(self) -> Result<(u8, u16), Error> {
+        let ResetMode::Bootsel {
+            disable_msd,
+            disable_picoboot,
+            activity_led,
+        } = self
+        else {
+            return Ok((RESET_REQUEST_FLASH, 0));
+        };
+        let mut value = 0;
+        if disable_msd {
+            value |= BOOTSEL_DISABLE_MSD as u16;
+        }
+        if disable_picoboot {
+            value |= BOOTSEL_DISABLE_PICOBOOT as u16;
+        }
+        if let Some(pin) = activity_led {
+            if pin >= MAX_GPIO {
+                return Err(Error::InvalidReboot(
+                    "the activity LED must be on one of GPIOs 0-47",
+                ));
+            }
+            value |= RESET_BOOTSEL_GPIO_SPECIFIED | (pin as u16) << RESET_BOOTSEL_GPIO_SHIFT;
+        }
+        Ok((RESET_REQUEST_BOOTSEL, value))
     }
+}
+
+/// Whether the device has the vendor reset interface in any of its configurations.
+/// Only the active one can be seen without opening the device
+pub(crate) fn has_reset_interface(device: &DeviceInfo) -> bool {
+    device
+        .interfaces()
+        .any(|i| i.class() == 0xff && i.subclass() == 0 && i.protocol() == 1)
+        || device
+            .open()
+            .is_ok_and(|handle| reset_interface(&handle).is_some())
+}
 
-    let device_handle = devices.first().unwrap().open().unwrap();
-    let reset_devices: Vec<u8> = device_handle.configurations().flat_map(|cfg| {
+/// The configuration value and interface number of the device's reset interface
+fn reset_interface(device: &Device) -> Option<(u8, u8)> {
+    device.configurations().find_map(|cfg| {
         cfg.interface_alt_settings()
-            .filter(|alt| alt.class() == 0xff && alt.subclass() == 0 && alt.protocol() == 1)
-            .map(|i| i.interface_number())
-    }).collect();
+            .find(|alt| alt.class() == 0xff && alt.subclass() == 0 && alt.protocol() == 1)
+            .map(|alt| (cfg.configuration_value(), alt.interface_number()))
+    })
+}
+
+/// Ask the only device running firmware with the reset interface that `selector`
+/// matches to reboot into `mode`. Returns the device as it was before the reset.
+///
+/// Only devices with the Raspberry Pi VID are considered unless `selector` names a VID.
+pub fn reset_usb_device(selector: &DeviceSelector, mode: ResetMode) -> Result<RpDevice, Error> {
+    let devices = reset_interface_devices(selector)?;
+    let device = selector.select(&devices)?;
+    send_reset(device, mode)?;
+    Ok(RpDevice::new(device, DeviceMode::Application))
+}
+
+/// Reset the device `selector` matches into BOOTSEL mode with every interface, as
+/// [`reset_usb_device`] does, then wait up to `timeout` for it to come back. Returns a
+/// selector for the same board in BOOTSEL mode, found again by its port path or else
+/// its serial number, and whether it had to be reset.
+///
+/// A matching board that is already in BOOTSEL mode is left as it is.
+pub fn reset_to_bootsel(
+    selector: &DeviceSelector,
+    timeout: Duration,
+) -> Result<(DeviceSelector, bool), Error> {
+    let devices = reset_interface_devices(selector)?;
+    let device = match selector.select(&devices) {
+        Ok(device) => device,
+        Err(Error::NoDevice) => {
+            let bootsel = picoboot_devices()?;
+            let device = selector.select(&bootsel)?;
+            return Ok((RpDevice::new(device, DeviceMode::Bootsel).selector(), false));
+        }
+        Err(e) => return Err(e),
+    };
+    // the bootrom reports the board's unique ID as its serial number, as the SDK
+    // does, but a port path is surer. Failing both, take the only BOOTSEL device
+    let same_board = match (port_path(device), device.serial_number()) {
+        (Some(port), _) => DeviceSelector {
+            port: Some(port),
+            ..Default::default()
+        },
+        (None, serial) => DeviceSelector {
+            serial: serial.map(String::from),
+            ..Default::default()
+        },
+    };
+    send_reset(device, ResetMode::BOOTSEL)?;
+
+    let device = wait_for_device(&same_board, &[DeviceMode::Bootsel], Some(timeout))?;
+    Ok((device.selector(), true))
+}
+
+/// The devices `selector` matches that have the reset interface. The selector is
+/// applied first, as finding the interface can mean opening the device
+fn reset_interface_devices(selector: &DeviceSelector) -> Result<Vec<DeviceInfo>, Error> {
+    Ok(nusb::list_devices()
+        .map_err(Error::Usb)?
+        .filter(|d| selector.vid.is_some() || d.vendor_id() == RP_VID)
+        .filter(|d| selector.matches(d))
+        .filter(has_reset_interface)
+        .collect())
+}
+
+fn send_reset(device: &DeviceInfo, mode: ResetMode) -> Result<(), Error> {
+    let (request, value) = mode.request()?;
+    let device_handle = device.open().map_err(Error::Usb)?;
+    let (config, iface) = reset_interface(&device_handle).ok_or(Error::NoResetInterface)?;
+    let active = device_handle
+        .active_configuration()
+        .map(|cfg| cfg.configuration_value());
+    if active.ok() != Some(config) {
+        device_handle
+            .set_configuration(config)
+            .map_err(Error::Usb)?;
+    }
+
+    let d = device_handle
+        .claim_interface(iface)
+        .or_else(|_| device_handle.detach_and_claim_interface(iface))
+        .map_err(Error::Usb)?;
+    // The device resets as soon as it handles the request, so it may never
+    // complete the status stage. Failures here are expected and ignored.
+    let _result = block_on(d.control_out(ControlOut {
+        control_type: ControlType::Class,
+        recipient: Recipient::Interface,
+        request,
+        value,
+        index: iface as u16,
+        data: &[],
+    }));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    if reset_devices.is_empty() {
-        println!("A device was found with a Raspberry Pi VendorID, but it did not have the USB Reset interface");
-        return;
+    #[test]
+    fn reset_requests() {
+        assert_eq!(ResetMode::Application.request().unwrap(), (0x02, 0));
+        assert_eq!(ResetMode::BOOTSEL.request().unwrap(), (0x01, 0));
+        let bootsel = |disable_msd, disable_picoboot, activity_led| {
+            ResetMode::Bootsel {
+                disable_msd,
+                disable_picoboot,
+                activity_led,
+            }
+            .request()
+        };
+        assert_eq!(bootsel(true, false, None).unwrap(), (0x01, 0x01));
+        assert_eq!(bootsel(false, true, None).unwrap(), (0x01, 0x02));
+        // the pin goes above the flag, so pin 0 is still told apart from no LED
+        assert_eq!(bootsel(false, false, Some(0)).unwrap(), (0x01, 0x100));
+        assert_eq!(
+            bootsel(true, false, Some(25)).unwrap(),
+            (0x01, 0x01 | 0x100 | 25 << 9)
+        );
+        assert_eq!(
+            bootsel(false, true, Some(47)).unwrap(),
+            (0x01, 0x02 | 0x100 | 47 << 9)
+        );
     }
 
-    println!("Resetting pico...");
-    for iface in reset_devices {
-        let d = device_handle
-            .claim_interface(iface)
-            .or(device_handle.detach_and_claim_interface(iface))
-            .expect("could not claim interface");
-        let _result = block_on(d.control_out(ControlOut {
-            control_type: ControlType::Class,
-            recipient: Recipient::Interface,
-            request: RESET_REQUEST_BOOTSEL,
-            value: 0,
-            index: iface as u16,
-            data: &[],
-        }));
+    #[test]
+    fn reset_activity_led_must_be_a_gpio() {
+        let request = ResetMode::Bootsel {
+            disable_msd: false,
+            disable_picoboot: false,
+            activity_led: Some(48),
+        }
+        .request();
+        assert!(matches!(request, Err(Error::InvalidReboot(_))));
     }
 }
@@ -0,0 +1,57 @@
+/// The stages of a load, in the order they happen. A verify or save has only the one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Phase {
+    Erase,
+    Program,
+    Verify,
+    Reboot,
+    /// Reading memory to save it
+    Read,
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Phase::Erase => "Erasing",
+            Phase::Program => "Programming",
+            Phase::Verify => "Verifying",
+            Phase::Reboot => "Rebooting",
+            Phase::Read => "Reading",
+        })
+    }
+}
+
+/// What a load is up to, as reported to a [`ProgressObserver`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProgressEvent {
+    /// `phase` is starting, and will cover `total` bytes. Phases with nothing to do
+    /// are skipped, apart from the reboot, which has no bytes
+    Start { phase: Phase, total: u64 },
+    /// The sector, or for RAM the 4KiB, at `addr` is done, bringing the current phase
+    /// to `done` of `total` bytes
+    Sector {
+        phase: Phase,
+        addr: u32,
+        done: u64,
+        total: u64,
+    },
+    /// The load, verify or save is over. After a load, the device has been told to reboot
+    Finished,
+}
+
+/// Receives [`ProgressEvent`]s as [`PicoTool`](crate::PicoTool) works through a load,
+/// verify or save.
+///
+/// Implemented for closures, so `tool.set_progress(Some(Box::new(|e| println!("{e:?}"))))`
+/// is enough to watch a load.
+pub trait ProgressObserver {
+    fn event(&mut self, event: ProgressEvent);
+}
+
+impl<F: FnMut(ProgressEvent)> ProgressObserver for F {
+    fn event(&mut self, event: ProgressEvent) {
+        self(event)
+    }
+}
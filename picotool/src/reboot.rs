@@ -0,0 +1,55 @@
+/// Where a device goes when it is told to reboot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebootMode {
+    /// Run the application in flash
+    Application,
+    /// Come back up in BOOTSEL mode
+    Bootsel {
+        disable_msd: bool,
+        disable_picoboot: bool,
+        /// GPIO to light while the bootloader is busy
+        activity_led: Option<ActivityLed>,
+    },
+    /// Start running at `pc` with the stack pointer at `sp`, leaving RAM as it is
+    PcSp { pc: u32, sp: u32 },
+    /// RP2350 only: boot the image of `size` bytes already loaded at `start` in SRAM
+    RamImage { start: u32, size: u32 },
+    /// RP2350 only: boot normally, treating the image at `buffer` in flash as one that
+    /// has just been written, so its version can be checked and committed
+    FlashUpdate { buffer: u32 },
+}
+
+/// A GPIO the bootloader drives to show USB activity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActivityLed {
+    pub pin: u8,
+    /// RP2350 only: drive the pin low rather than high when active
+    pub active_low: bool,
+}
+
+// the RP2040 can only get back to BOOTSEL through the bootrom's reset_usb_boot
+// function, so this little Thumb program is loaded into SRAM and run to call it:
+//
+//     movs r0, #0x14      ; the ROM function table pointer
+//     ldrh r0, [r0]
+//     movs r2, #0x18      ; the ROM table lookup function pointer
+//     ldrh r2, [r2]
+//     ldr  r1, =0x4255    ; 'UB', for reset_usb_boot
+//     blx  r2
+//     mov  r3, r0
+//     ldr  r0, =gpio_mask
+//     ldr  r1, =disable_mask
+//     blx  r3
+//     b    .
+const RESET_USB_BOOT_CODE: [u16; 12] = [
+    0x2014, 0x8800, 0x2218, 0x8812, 0x4903, 0x4790, 0x4603, 0x4803, 0x4903, 0x4798, 0xe7fe, 0xbf00,
+];
+const ROM_CODE_RESET_USB_BOOT: u32 = u32::from_le_bytes([b'U', b'B', 0, 0]);
+
+/// The program above followed by its literal pool
+pub(crate) fn reset_usb_boot_program(gpio_mask: u32, disable_mask: u32) -> Vec<u8> {
+    let code = RESET_USB_BOOT_CODE.iter().flat_map(|h| h.to_le_bytes());
+    let literals = [ROM_CODE_RESET_USB_BOOT, gpio_mask, disable_mask];
+    code.chain(literals.iter().flat_map(|w| w.to_le_bytes()))
+        .collect()
+}
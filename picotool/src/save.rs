@@ -0,0 +1,39 @@
+use crate::uf2::Uf2Family;
+use std::path::Path;
+
+/// Which part of the device's memory to save
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveRange {
+    /// The image at the start of flash. It ends where its binary info says it does, or
+    /// failing that after the last sector that isn't blank
+    Program,
+    /// All of flash, its size as the device reports it
+    Flash,
+    /// All of main SRAM
+    Ram,
+    /// The bootrom
+    Rom,
+    /// Anything readable from `start` up to but not including `end`
+    Range { start: u32, end: u32 },
+}
+
+/// File formats memory can be saved in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveFormat {
+    Bin,
+    /// UF2 blocks tagged with the given family, or if None the connected chip's own
+    Uf2(Option<Uf2Family>),
+    /// An Arm executable with one segment, loaded where the memory was read from
+    Elf,
+}
+
+impl SaveFormat {
+    /// Pick the format from a file's extension, falling back to a raw binary
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("uf2") => SaveFormat::Uf2(None),
+            Some(ext) if ext.eq_ignore_ascii_case("elf") => SaveFormat::Elf,
+            _ => SaveFormat::Bin,
+        }
+    }
+}
@@ -0,0 +1,654 @@
+// see https://github.com/microsoft/uf2 for the UF2 format
+
+use crate::TargetID;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+const UF2_MAGIC_START0: u32 = 0x0A324655;
+const UF2_MAGIC_START1: u32 = 0x9E5D5157;
+const UF2_MAGIC_END: u32 = 0x0AB16F30;
+const UF2_BLOCK_SIZE: usize = 512;
+const UF2_MAX_PAYLOAD: usize = 476;
+// what the SDK and the bootroms write, one flash page per block
+const UF2_WRITE_PAYLOAD: usize = 256;
+
+pub const UF2_FLAG_NOT_MAIN_FLASH: u32 = 0x0000_0001;
+pub const UF2_FLAG_FILE_CONTAINER: u32 = 0x0000_1000;
+pub const UF2_FLAG_FAMILY_ID_PRESENT: u32 = 0x0000_2000;
+pub const UF2_FLAG_MD5_PRESENT: u32 = 0x0000_4000;
+pub const UF2_FLAG_EXTENSION_TAGS_PRESENT: u32 = 0x0000_8000;
+
+/// UF2 family IDs the RP bootroms know about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Uf2Family {
+    Rp2040,
+    /// Written at its target address on any chip, regardless of partitions
+    Absolute,
+    /// Not code, loadable on any chip
+    Data,
+    Rp2350ArmS,
+    Rp2350Riscv,
+    Rp2350ArmNs,
+}
+
+impl Uf2Family {
+    const ALL: [Uf2Family; 6] = [
+        Uf2Family::Rp2040,
+        Uf2Family::Absolute,
+        Uf2Family::Data,
+        Uf2Family::Rp2350ArmS,
+        Uf2Family::Rp2350Riscv,
+        Uf2Family::Rp2350ArmNs,
+    ];
+
+    pub const fn id(self) -> u32 {
+        match self {
+            Uf2Family::Rp2040 => 0xe48bff56,
+            Uf2Family::Absolute => 0xe48bff57,
+            Uf2Family::Data => 0xe48bff58,
+            Uf2Family::Rp2350ArmS => 0xe48bff59,
+            Uf2Family::Rp2350Riscv => 0xe48bff5a,
+            Uf2Family::Rp2350ArmNs => 0xe48bff5b,
+        }
+    }
+
+    pub fn from_id(id: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.id() == id)
+    }
+
+    /// The name picotool uses for the family
+    pub const fn name(self) -> &'static str {
+        match self {
+            Uf2Family::Rp2040 => "rp2040",
+            Uf2Family::Absolute => "absolute",
+            Uf2Family::Data => "data",
+            Uf2Family::Rp2350ArmS => "rp2350-arm-s",
+            Uf2Family::Rp2350Riscv => "rp2350-riscv",
+            Uf2Family::Rp2350ArmNs => "rp2350-arm-ns",
+        }
+    }
+
+    /// Families that can be loaded onto `target`, most preferred first
+    pub fn for_target(target: TargetID) -> &'static [Uf2Family] {
+        match target {
+            TargetID::Rp2040 => &[Uf2Family::Rp2040, Uf2Family::Absolute, Uf2Family::Data],
+            // the RP2350 boots Arm unless told otherwise
+            TargetID::Rp2350 => &[
+                Uf2Family::Rp2350ArmS,
+                Uf2Family::Rp2350ArmNs,
+                Uf2Family::Rp2350Riscv,
+                Uf2Family::Absolute,
+                Uf2Family::Data,
+            ],
+        }
+    }
+}
+
+impl std::fmt::Display for Uf2Family {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Uf2Family {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|f| f.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|f| f.name()).collect();
+                format!("unknown family '{s}', expected one of {}", names.join(", "))
+            })
+    }
+}
+
+/// Describe a list of family IDs by name where they are known
+pub(crate) fn family_names(ids: &[Option<u32>]) -> String {
+    let names: Vec<String> = ids
+        .iter()
+        .map(|id| match id {
+            Some(id) => Uf2Family::from_id(*id).map_or(format!("{id:#010x}"), |f| f.to_string()),
+            None => "none".to_string(),
+        })
+        .collect();
+    names.join(", ")
+}
+
+/// Ways a UF2 file can be malformed
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Uf2Error {
+    #[error("file is {0} bytes long, which is not a whole number of 512 byte blocks")]
+    Length(usize),
+    #[error("file contains no UF2 blocks")]
+    Empty,
+    #[error("block {index} has bad magic numbers")]
+    Magic { index: usize },
+    #[error("block {index} has a payload of {size} bytes, more than the 476 that fit")]
+    PayloadSize { index: usize, size: u32 },
+    #[error("block {index} is numbered {block_no} of {num_blocks}")]
+    BlockNumber {
+        index: usize,
+        block_no: u32,
+        num_blocks: u32,
+    },
+    #[error("block {index} repeats block number {block_no}")]
+    DuplicateBlock { index: usize, block_no: u32 },
+    #[error("expected {expected} blocks for family {family_id:08x?}, found {found}")]
+    MissingBlocks {
+        family_id: Option<u32>,
+        expected: u32,
+        found: u32,
+    },
+    #[error("block {index} overlaps another block at {addr:#010x}")]
+    Overlap { index: usize, addr: u32 },
+    #[error("block {index} at {addr:#010x} runs past the end of the address space")]
+    AddressOverflow { index: usize, addr: u32 },
+    #[error(
+        "block at {addr:#010x} for family {family_id:08x?} overlaps the image it's loaded with"
+    )]
+    FamilyOverlap { family_id: Option<u32>, addr: u32 },
+}
+
+/// One 512 byte UF2 block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uf2Block {
+    pub flags: u32,
+    pub target_addr: u32,
+    pub block_no: u32,
+    pub num_blocks: u32,
+    /// Only present when the block sets `UF2_FLAG_FAMILY_ID_PRESENT`
+    pub family_id: Option<u32>,
+    pub data: Vec<u8>,
+}
+
+impl Uf2Block {
+    /// Whether the block holds data meant for the device's memory, rather than
+    /// a file container entry or data the bootloader should skip
+    pub fn is_programmable(&self) -> bool {
+        self.flags & (UF2_FLAG_NOT_MAIN_FLASH | UF2_FLAG_FILE_CONTAINER) == 0
+    }
+}
+
+/// The programmable blocks of one family in a UF2 file, keyed by target address
+#[derive(Debug, Clone)]
+pub struct Uf2Image {
+    pub family_id: Option<u32>,
+    pub blocks: BTreeMap<u32, Uf2Block>,
+}
+
+impl Uf2Image {
+    /// The programmable data in the image, merged into contiguous address ranges
+    pub fn ranges(&self) -> BTreeMap<u32, Vec<u8>> {
+        let mut ranges: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+        let mut last: Option<(u32, u32)> = None; // (start, end) of the range being built
+        for block in self.blocks.values() {
+            match last {
+                Some((start, end)) if end == block.target_addr => {
+                    let range = ranges.get_mut(&start).expect("range was inserted");
+                    range.extend_from_slice(&block.data);
+                    last = Some((start, end + block.data.len() as u32));
+                }
+                _ => {
+                    ranges.insert(block.target_addr, block.data.clone());
+                    last = Some((
+                        block.target_addr,
+                        block.target_addr + block.data.len() as u32,
+                    ));
+                }
+            }
+        }
+        ranges
+    }
+}
+
+/// Pick the image to load onto `target`: the one for the most preferred chip family
+/// it supports, or failing that one without a family ID, which can't be checked, merged
+/// with any absolute and data images, which every chip loads.
+///
+/// Images for families the chip can't run are never chosen. Returns `Ok(None)` if
+/// nothing in the file is for `target`.
+pub fn select_image(images: &[Uf2Image], target: TargetID) -> Result<Option<Uf2Image>, Uf2Error> {
+    let shared = [Uf2Family::Absolute.id(), Uf2Family::Data.id()];
+    let chip = Uf2Family::for_target(target)
+        .iter()
+        .filter(|family| !shared.contains(&family.id()))
+        .find_map(|family| images.iter().find(|i| i.family_id == Some(family.id())))
+        .or_else(|| images.iter().find(|i| i.family_id.is_none()));
+    let mut chosen = chip.into_iter().chain(
+        images
+            .iter()
+            .filter(|i| i.family_id.is_some_and(|id| shared.contains(&id))),
+    );
+
+    let Some(mut image) = chosen.next().cloned() else {
+        return Ok(None);
+    };
+    for other in chosen {
+        for (&addr, block) in &other.blocks {
+            let end = addr + block.data.len() as u32;
+            let before = image.blocks.range(..=addr).next_back();
+            let after = image.blocks.range(addr..).next();
+            if before.is_some_and(|(&a, b)| a + b.data.len() as u32 > addr)
+                || after.is_some_and(|(&a, _)| a < end)
+            {
+                return Err(Uf2Error::FamilyOverlap {
+                    family_id: other.family_id,
+                    addr,
+                });
+            }
+            image.blocks.insert(addr, block.clone());
+        }
+    }
+    Ok(Some(image))
+}
+
+/// Parse a UF2 file into one image per family, in the order each family first appears.
+///
+/// Within a family, block numbers must cover `0..num_blocks` exactly once and no two
+/// blocks may overlap.
+pub fn parse_uf2(bytes: &[u8]) -> Result<Vec<Uf2Image>, Uf2Error> {
+    if !bytes.len().is_multiple_of(UF2_BLOCK_SIZE) {
+        return Err(Uf2Error::Length(bytes.len()));
+    }
+    if bytes.is_empty() {
+        return Err(Uf2Error::Empty);
+    }
+
+    let total_blocks = bytes.len() / UF2_BLOCK_SIZE;
+    let mut images: Vec<Uf2Image> = vec![];
+    // block numbers seen so far for each image
+    let mut seen: Vec<Vec<bool>> = vec![];
+    for (index, raw) in bytes.chunks_exact(UF2_BLOCK_SIZE).enumerate() {
+        let block = parse_block(index, raw)?;
+        let bad_number = Uf2Error::BlockNumber {
+            index,
+            block_no: block.block_no,
+            num_blocks: block.num_blocks,
+        };
+        if block.block_no >= block.num_blocks || block.num_blocks as usize > total_blocks {
+            return Err(bad_number);
+        }
+
+        let image_index = match images.iter().position(|i| i.family_id == block.family_id) {
+            Some(i) => i,
+            None => {
+                images.push(Uf2Image {
+                    family_id: block.family_id,
+                    blocks: BTreeMap::new(),
+                });
+                seen.push(vec![false; block.num_blocks as usize]);
+                images.len() - 1
+            }
+        };
+
+        let seen = &mut seen[image_index];
+        if block.num_blocks as usize != seen.len() {
+            return Err(bad_number);
+        }
+        if std::mem::replace(&mut seen[block.block_no as usize], true) {
+            return Err(Uf2Error::DuplicateBlock {
+                index,
+                block_no: block.block_no,
+            });
+        }
+
+        let blocks = &mut images[image_index].blocks;
+        if block.is_programmable() && !block.data.is_empty() {
+            let start = block.target_addr as u64;
+            let end = start + block.data.len() as u64;
+            if end > u32::MAX as u64 {
+                return Err(Uf2Error::AddressOverflow {
+                    index,
+                    addr: block.target_addr,
+                });
+            }
+            let before = blocks.range(..=block.target_addr).next_back();
+            let after = blocks.range(block.target_addr..).next();
+            let overlaps_before =
+                before.is_some_and(|(&addr, b)| addr as u64 + b.data.len() as u64 > start);
+            let overlaps_after = after.is_some_and(|(&addr, _)| (addr as u64) < end);
+            if overlaps_before || overlaps_after {
+                return Err(Uf2Error::Overlap {
+                    index,
+                    addr: block.target_addr,
+                });
+            }
+            blocks.insert(block.target_addr, block);
+        }
+    }
+
+    for (image, seen) in images.iter().zip(&seen) {
+        let found = seen.iter().filter(|&&s| s).count() as u32;
+        if found != seen.len() as u32 {
+            return Err(Uf2Error::MissingBlocks {
+                family_id: image.family_id,
+                expected: seen.len() as u32,
+                found,
+            });
+        }
+    }
+    Ok(images)
+}
+
+/// Encode `data` as a UF2 file that writes it at `addr`, 256 bytes to a block.
+/// Padding is added at the end to fill the last block
+pub fn write_uf2(addr: u32, data: &[u8], family: Uf2Family) -> Vec<u8> {
+    let num_blocks = data.len().div_ceil(UF2_WRITE_PAYLOAD) as u32;
+    let mut out = Vec::with_capacity(num_blocks as usize * UF2_BLOCK_SIZE);
+    for (block_no, chunk) in data.chunks(UF2_WRITE_PAYLOAD).enumerate() {
+        let header = [
+            UF2_MAGIC_START0,
+            UF2_MAGIC_START1,
+            UF2_FLAG_FAMILY_ID_PRESENT,
+            addr + (block_no * UF2_WRITE_PAYLOAD) as u32,
+            UF2_WRITE_PAYLOAD as u32,
+            block_no as u32,
+            num_blocks,
+            family.id(),
+        ];
+        let start = out.len();
+        out.extend(header.iter().flat_map(|w| w.to_le_bytes()));
+        out.extend_from_slice(chunk);
+        out.resize(start + UF2_BLOCK_SIZE - 4, 0);
+        out.extend_from_slice(&UF2_MAGIC_END.to_le_bytes());
+    }
+    out
+}
+
+fn parse_block(index: usize, raw: &[u8]) -> Result<Uf2Block, Uf2Error> {
+    let word = |i: usize| u32::from_le_bytes(raw[i * 4..i * 4 + 4].try_into().unwrap());
+    if word(0) != UF2_MAGIC_START0
+        || word(1) != UF2_MAGIC_START1
+        || word(UF2_BLOCK_SIZE / 4 - 1) != UF2_MAGIC_END
+    {
+        return Err(Uf2Error::Magic { index });
+    }
+
+    let flags = word(2);
+    let payload_size = word(4);
+    if payload_size as usize > UF2_MAX_PAYLOAD {
+        return Err(Uf2Error::PayloadSize {
+            index,
+            size: payload_size,
+        });
+    }
+    Ok(Uf2Block {
+        flags,
+        target_addr: word(3),
+        block_no: word(5),
+        num_blocks: word(6),
+        // without the flag this word is the file size, or unused
+        family_id: (flags & UF2_FLAG_FAMILY_ID_PRESENT != 0).then(|| word(7)),
+        data: raw[32..32 + payload_size as usize].to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RP2040: u32 = 0xe48bff56;
+
+    /// A raw block with a family ID and `len` bytes of payload
+    fn block(addr: u32, block_no: u32, num_blocks: u32, family: u32, len: usize) -> Vec<u8> {
+        let header = [
+            UF2_MAGIC_START0,
+            UF2_MAGIC_START1,
+            UF2_FLAG_FAMILY_ID_PRESENT,
+            addr,
+            len as u32,
+            block_no,
+            num_blocks,
+            family,
+        ];
+        let mut raw: Vec<u8> = header.iter().flat_map(|w| w.to_le_bytes()).collect();
+        raw.extend((0..len).map(|i| (addr as usize + i) as u8));
+        raw.resize(UF2_BLOCK_SIZE - 4, 0);
+        raw.extend_from_slice(&UF2_MAGIC_END.to_le_bytes());
+        raw
+    }
+
+    fn file(blocks: &[Vec<u8>]) -> Vec<u8> {
+        blocks.concat()
+    }
+
+    #[test]
+    fn write_then_parse() {
+        let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let images = parse_uf2(&write_uf2(0x1000_0000, &data, Uf2Family::Rp2040)).unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].family_id, Some(RP2040));
+        assert_eq!(images[0].blocks.len(), 3);
+        // the last block is padded out to a whole page
+        let ranges = images[0].ranges();
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[&0x1000_0000][..600], data[..]);
+        assert_eq!(ranges[&0x1000_0000].len(), 768);
+    }
+
+    #[test]
+    fn ranges_merge_contiguous_blocks_only() {
+        // out of order in the file, with a gap before the last
+        let bytes = file(&[
+            block(0x1000_0100, 1, 3, RP2040, 256),
+            block(0x1000_0000, 0, 3, RP2040, 256),
+            block(0x1000_1000, 2, 3, RP2040, 256),
+        ]);
+        let ranges = parse_uf2(&bytes).unwrap()[0].ranges();
+        assert_eq!(
+            ranges.keys().copied().collect::<Vec<_>>(),
+            [0x1000_0000, 0x1000_1000]
+        );
+        assert_eq!(ranges[&0x1000_0000].len(), 512);
+        assert_eq!(ranges[&0x1000_1000].len(), 256);
+    }
+
+    #[test]
+    fn families_are_split_into_images() {
+        let rp2350 = Uf2Family::Rp2350ArmS.id();
+        let bytes = file(&[
+            block(0x1000_0000, 0, 1, rp2350, 256),
+            block(0x1000_0000, 0, 1, RP2040, 256),
+        ]);
+        let images = parse_uf2(&bytes).unwrap();
+        let families: Vec<_> = images.iter().map(|i| i.family_id).collect();
+        assert_eq!(families, [Some(rp2350), Some(RP2040)]);
+        let selected = select_image(&images, TargetID::Rp2040).unwrap().unwrap();
+        assert_eq!(selected.family_id, Some(RP2040));
+    }
+
+    #[test]
+    fn absolute_and_data_images_are_loaded_with_the_chip_image() {
+        let rp2350 = Uf2Family::Rp2350ArmS.id();
+        let absolute = Uf2Family::Absolute.id();
+        let data = Uf2Family::Data.id();
+        let bytes = file(&[
+            block(0x1000_1000, 0, 1, data, 256),
+            block(0x1000_0000, 0, 1, rp2350, 256),
+            block(0x1000_0000, 0, 1, RP2040, 256),
+            block(0x1000_0100, 0, 1, absolute, 256),
+        ]);
+        let images = parse_uf2(&bytes).unwrap();
+        for target in [TargetID::Rp2040, TargetID::Rp2350] {
+            let selected = select_image(&images, target).unwrap().unwrap();
+            let ranges = selected.ranges();
+            assert_eq!(
+                ranges.keys().copied().collect::<Vec<_>>(),
+                [0x1000_0000, 0x1000_1000]
+            );
+            assert_eq!(ranges[&0x1000_0000].len(), 512);
+        }
+
+        // without a chip image the shared ones are still loaded
+        let images = parse_uf2(&file(&[block(0x1000_1000, 0, 1, data, 256)])).unwrap();
+        let selected = select_image(&images, TargetID::Rp2040).unwrap().unwrap();
+        assert_eq!(selected.family_id, Some(data));
+
+        // but they may not overlap the chip image
+        let bytes = file(&[
+            block(0x1000_0000, 0, 1, RP2040, 256),
+            block(0x1000_0080, 0, 1, absolute, 256),
+        ]);
+        let images = parse_uf2(&bytes).unwrap();
+        assert!(matches!(
+            select_image(&images, TargetID::Rp2040),
+            Err(Uf2Error::FamilyOverlap {
+                family_id: Some(_),
+                addr: 0x1000_0080
+            })
+        ));
+
+        let images = parse_uf2(&file(&[block(0x1000_0000, 0, 1, rp2350, 256)])).unwrap();
+        assert!(select_image(&images, TargetID::Rp2040).unwrap().is_none());
+    }
+
+    #[test]
+    fn unprogrammable_blocks_are_skipped() {
+        let mut container = block(0x1000_0000, 1, 2, RP2040, 256);
+        container[8..12]
+            .copy_from_slice(&(UF2_FLAG_FAMILY_ID_PRESENT | UF2_FLAG_NOT_MAIN_FLASH).to_le_bytes());
+        let bytes = file(&[block(0x1000_0000, 0, 2, RP2040, 256), container]);
+        let images = parse_uf2(&bytes).unwrap();
+        assert_eq!(images[0].blocks.len(), 1);
+    }
+
+    #[test]
+    fn length() {
+        let bytes = block(0x1000_0000, 0, 1, RP2040, 256);
+        assert!(matches!(
+            parse_uf2(&bytes[..500]),
+            Err(Uf2Error::Length(500))
+        ));
+        assert!(matches!(parse_uf2(&[]), Err(Uf2Error::Empty)));
+    }
+
+    #[test]
+    fn magic() {
+        let mut bytes = file(&[
+            block(0x1000_0000, 0, 2, RP2040, 256),
+            block(0x1000_0100, 1, 2, RP2040, 256),
+        ]);
+        bytes[UF2_BLOCK_SIZE * 2 - 1] ^= 0xff;
+        assert!(matches!(
+            parse_uf2(&bytes),
+            Err(Uf2Error::Magic { index: 1 })
+        ));
+    }
+
+    #[test]
+    fn payload_size() {
+        let mut bytes = block(0x1000_0000, 0, 1, RP2040, 256);
+        bytes[16..20].copy_from_slice(&477u32.to_le_bytes());
+        assert!(matches!(
+            parse_uf2(&bytes),
+            Err(Uf2Error::PayloadSize {
+                index: 0,
+                size: 477
+            })
+        ));
+    }
+
+    #[test]
+    fn block_number() {
+        // past the end of its own count
+        let bytes = block(0x1000_0000, 1, 1, RP2040, 256);
+        assert!(matches!(
+            parse_uf2(&bytes),
+            Err(Uf2Error::BlockNumber { index: 0, .. })
+        ));
+        // more blocks claimed than the file holds
+        let bytes = block(0x1000_0000, 0, 2, RP2040, 256);
+        assert!(matches!(
+            parse_uf2(&bytes),
+            Err(Uf2Error::BlockNumber { index: 0, .. })
+        ));
+        // a count that changes part way through a family
+        let bytes = file(&[
+            block(0x1000_0000, 0, 2, RP2040, 256),
+            block(0x1000_0100, 1, 3, RP2040, 256),
+            block(0x1000_0200, 2, 3, RP2040, 256),
+        ]);
+        assert!(matches!(
+            parse_uf2(&bytes),
+            Err(Uf2Error::BlockNumber { index: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn duplicate_block() {
+        let bytes = file(&[
+            block(0x1000_0000, 0, 2, RP2040, 256),
+            block(0x1000_0100, 0, 2, RP2040, 256),
+        ]);
+        assert!(matches!(
+            parse_uf2(&bytes),
+            Err(Uf2Error::DuplicateBlock {
+                index: 1,
+                block_no: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn missing_blocks() {
+        let rp2350 = Uf2Family::Rp2350ArmS.id();
+        let bytes = file(&[
+            block(0x1000_0000, 0, 2, RP2040, 256),
+            block(0x1000_0000, 0, 1, rp2350, 256),
+        ]);
+        assert!(matches!(
+            parse_uf2(&bytes),
+            Err(Uf2Error::MissingBlocks {
+                family_id: Some(RP2040),
+                expected: 2,
+                found: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn address_overflow() {
+        let bytes = file(&[block(0xffff_ff80, 0, 1, RP2040, 256)]);
+        assert!(matches!(
+            parse_uf2(&bytes),
+            Err(Uf2Error::AddressOverflow {
+                index: 0,
+                addr: 0xffff_ff80
+            })
+        ));
+    }
+
+    #[test]
+    fn overlap() {
+        // the second block starts inside the first
+        let bytes = file(&[
+            block(0x1000_0000, 0, 2, RP2040, 256),
+            block(0x1000_0080, 1, 2, RP2040, 256),
+        ]);
+        assert!(matches!(
+            parse_uf2(&bytes),
+            Err(Uf2Error::Overlap {
+                index: 1,
+                addr: 0x1000_0080
+            })
+        ));
+        // and the second runs into the first
+        let bytes = file(&[
+            block(0x1000_0100, 0, 2, RP2040, 256),
+            block(0x1000_0080, 1, 2, RP2040, 256),
+        ]);
+        assert!(matches!(
+            parse_uf2(&bytes),
+            Err(Uf2Error::Overlap { index: 1, .. })
+        ));
+        // the same addresses in different families are fine
+        let bytes = file(&[
+            block(0x1000_0000, 0, 1, RP2040, 256),
+            block(0x1000_0000, 0, 1, Uf2Family::Data.id(), 256),
+        ]);
+        assert_eq!(parse_uf2(&bytes).unwrap().len(), 2);
+    }
+}
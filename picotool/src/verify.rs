@@ -0,0 +1,74 @@
+// how many runs of differing bytes a report keeps, and how much of each
+const MAX_MISMATCHES: usize = 8;
+const MAX_MISMATCH_LEN: usize = 64;
+// matching bytes that end a run, so nearby differences are reported together
+const MISMATCH_GAP: usize = 16;
+
+/// A run of bytes where the device differs from the image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Address of the first differing byte
+    pub addr: u32,
+    /// What the image holds from `addr`, up to the last differing byte of the run or
+    /// 64 bytes, whichever is shorter. Matching bytes inside the run are included
+    pub expected: Vec<u8>,
+    /// What the device holds over the same bytes
+    pub actual: Vec<u8>,
+}
+
+impl Mismatch {
+    fn end(&self) -> u64 {
+        self.addr as u64 + self.expected.len() as u64
+    }
+}
+
+/// The result of comparing a device's memory with an image
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Bytes of the image that were compared
+    pub bytes_compared: u64,
+    /// Bytes that differ, across the whole image
+    pub bytes_different: u64,
+    /// The first few runs of differing bytes, in address order
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl VerifyReport {
+    /// Whether the device holds exactly what the image does
+    pub fn matches(&self) -> bool {
+        self.bytes_different == 0
+    }
+
+    /// Compare `actual`, read from the device at `addr`, with `expected`. Chunks must
+    /// be added in address order for runs to be joined across them
+    pub(crate) fn add(&mut self, addr: u32, expected: &[u8], actual: &[u8]) {
+        self.bytes_compared += expected.len() as u64;
+        for (i, (&e, &a)) in expected.iter().zip(actual).enumerate() {
+            if e == a {
+                continue;
+            }
+            self.bytes_different += 1;
+            let byte_addr = addr + i as u32;
+            let full = self.mismatches.len() >= MAX_MISMATCHES;
+            match self.mismatches.last_mut() {
+                // join on to the last run if it is close, and the bytes between are
+                // in this chunk
+                Some(last)
+                    if (addr as u64..=byte_addr as u64).contains(&last.end())
+                        && byte_addr as u64 <= last.end() + MISMATCH_GAP as u64 =>
+                {
+                    let from = (last.end() - addr as u64) as usize;
+                    let to = (i + 1).min(from + MAX_MISMATCH_LEN - last.expected.len());
+                    last.expected.extend_from_slice(&expected[from..to]);
+                    last.actual.extend_from_slice(&actual[from..to]);
+                }
+                _ if !full => self.mismatches.push(Mismatch {
+                    addr: byte_addr,
+                    expected: vec![e],
+                    actual: vec![a],
+                }),
+                _ => {}
+            }
+        }
+    }
+}
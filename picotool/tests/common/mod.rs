@@ -0,0 +1,63 @@
+// Helpers shared by the emulator tests and the benchmarks
+#![allow(dead_code)]
+
+use picotool::picoboot::emulator::EmulatedDevice;
+use picotool::{Error, PicobootStatus, TargetID, PICO_FLASH_START};
+use std::path::PathBuf;
+
+/// A directory of one test's own under the system temp dir, removed when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("picotool-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    /// A path for `name` inside the directory
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// `len` bytes of data that doesn't repeat or sit blank
+pub fn test_data(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed | 1;
+    (0..len)
+        .map(|_| {
+            // xorshift, so the data doesn't compress or repeat
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+pub fn flash_at(device: &EmulatedDevice, addr: u32, len: usize) -> Vec<u8> {
+    let offset = (addr - PICO_FLASH_START) as usize;
+    device.flash()[offset..offset + len].to_vec()
+}
+
+/// The same `target` board come back in BOOTSEL, as it is after a load reboots it
+pub fn replug(device: &EmulatedDevice, target: TargetID, serial: &str) -> EmulatedDevice {
+    let flash = device.flash();
+    let replugged = EmulatedDevice::with_flash_size(target, flash.len());
+    replugged.load_flash(PICO_FLASH_START, &flash);
+    replugged.set_serial_number(serial);
+    replugged
+}
+
+pub fn status(result: Result<impl std::fmt::Debug, Error>) -> PicobootStatus {
+    match result {
+        Err(Error::Status { status, .. }) => status,
+        other => panic!("expected a status error, got {other:?}"),
+    }
+}
@@ -0,0 +1,634 @@
+// Drives PicoTool against the emulated bootrom, end to end through the PICOBOOT
+// command layer
+
+mod common;
+
+use async_io::{block_on, Timer};
+use common::{flash_at, replug, status, test_data, TempDir};
+use futures_lite::FutureExt;
+use picotool::elf::write_elf;
+use picotool::ihex::write_ihex;
+use picotool::picoboot::emulator::{EmulatedDevice, RebootRequest, Timing};
+use picotool::picoboot::usb::{AsyncPicobootConnection, PicobootConnection};
+use picotool::reboot::{ActivityLed, RebootMode};
+use picotool::save::{SaveFormat, SaveRange};
+use picotool::uf2::{write_uf2, Uf2Family};
+use picotool::{
+    EraseRange, Error, PicoTool, PicobootStatus, TargetID, PICO_FLASH_START, PICO_SECTOR_SIZE,
+    PICO_SRAM_START, PICO_STACK_POINTER,
+};
+use std::time::Duration;
+
+#[test]
+fn bad_alignment() {
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let mut conn = PicobootConnection::with_transport(device);
+    conn.exit_xip().unwrap();
+    let status = status(conn.flash_erase(PICO_FLASH_START + 0x100, PICO_SECTOR_SIZE));
+    assert_eq!(status, PicobootStatus::BadAlignment);
+}
+
+#[test]
+fn invalid_address() {
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let mut conn = PicobootConnection::with_transport(device);
+    assert_eq!(
+        status(conn.flash_read(0x5000_0000, 16)),
+        PicobootStatus::InvalidAddress
+    );
+}
+
+#[test]
+fn status_of_a_failed_command() {
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let mut conn = PicobootConnection::with_transport(device.clone());
+    device.fail_next_command(PicobootStatus::NotPermitted);
+    assert!(matches!(
+        conn.flash_read(PICO_FLASH_START, 16),
+        Err(Error::Status {
+            status: PicobootStatus::NotPermitted,
+            cmd_id: 0x84,
+            ..
+        })
+    ));
+    // and the next command goes through once the interface is reset
+    conn.reset_interface().unwrap();
+    conn.flash_read(PICO_FLASH_START, 16).unwrap();
+}
+
+#[test]
+fn status_for_another_command() {
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let mut conn = PicobootConnection::with_transport(device.clone());
+    device.misreport_next_status();
+    match conn.exit_xip() {
+        Err(Error::StatusMismatch {
+            token,
+            cmd_id: 0x06,
+            status_token,
+            status_cmd_id: 0x06,
+        }) => assert_eq!(status_token, token - 1),
+        other => panic!("expected a status mismatch, got {other:?}"),
+    }
+}
+
+#[test]
+fn load_uf2() {
+    let dir = TempDir::new("load_uf2");
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let data = test_data(3 * PICO_SECTOR_SIZE as usize + 512, 1);
+    let path = dir.path("load.uf2");
+    std::fs::write(&path, write_uf2(PICO_FLASH_START, &data, Uf2Family::Rp2040)).unwrap();
+
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    let summary = tool.load(&path).unwrap();
+    assert_eq!(summary.sectors_written, 4);
+    assert_eq!(flash_at(&device, PICO_FLASH_START, data.len()), data);
+    assert_eq!(
+        device.reboot_requested(),
+        Some(RebootRequest::Reboot {
+            pc: 0,
+            sp: PICO_STACK_POINTER,
+            delay: 500
+        })
+    );
+}
+
+#[test]
+fn load_uf2_outside_memory() {
+    let dir = TempDir::new("load_uf2_outside_memory");
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let path = dir.path("outside.uf2");
+    // across the end of the 2MiB fitted, and then in peripheral space
+    let past_end = PICO_FLASH_START + (2 << 20) - 256;
+    for (addr, fits) in [(past_end, false), (0x4000_0000, true)] {
+        std::fs::write(
+            &path,
+            write_uf2(addr, &test_data(512, 18), Uf2Family::Rp2040),
+        )
+        .unwrap();
+        let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+        match tool.load(&path) {
+            Err(Error::OutOfFlash { addr: at, .. }) if !fits => assert_eq!(at, addr),
+            Err(Error::OutOfMemory { addr: at, .. }) if fits => assert_eq!(at, addr),
+            other => panic!("expected {addr:#x} to be refused, got {other:?}"),
+        }
+    }
+    assert!(device.flash().iter().all(|&b| b == 0xff));
+}
+
+#[test]
+fn load_uf2_for_another_chip() {
+    let dir = TempDir::new("load_uf2_for_another_chip");
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let path = dir.path("other.uf2");
+    let uf2 = write_uf2(PICO_FLASH_START, &test_data(256, 2), Uf2Family::Rp2350ArmS);
+    std::fs::write(&path, uf2).unwrap();
+
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    assert!(matches!(
+        tool.load(&path),
+        Err(Error::FamilyMismatch { .. })
+    ));
+    assert!(device.flash().iter().all(|&b| b == 0xff));
+}
+
+#[test]
+fn load_uf2_with_several_families() {
+    let dir = TempDir::new("load_uf2_with_several_families");
+    let code = test_data(1000, 19);
+    let other_code = test_data(1000, 20);
+    let data = test_data(300, 21);
+    let data_addr = PICO_FLASH_START + 0x10000;
+    let mut uf2 = write_uf2(PICO_FLASH_START, &other_code, Uf2Family::Rp2350ArmS);
+    uf2.extend(write_uf2(PICO_FLASH_START, &code, Uf2Family::Rp2040));
+    uf2.extend(write_uf2(data_addr, &data, Uf2Family::Data));
+    let path = dir.path("several.uf2");
+    std::fs::write(&path, uf2).unwrap();
+
+    for (target, code) in [(TargetID::Rp2040, &code), (TargetID::Rp2350, &other_code)] {
+        let device = EmulatedDevice::new(target);
+        let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+        let summary = tool.load(&path).unwrap();
+        assert_eq!(summary.sectors_written, 2);
+        assert_eq!(flash_at(&device, PICO_FLASH_START, code.len()), *code);
+        assert_eq!(flash_at(&device, data_addr, data.len()), data);
+    }
+}
+
+#[test]
+fn load_elf_to_flash() {
+    let dir = TempDir::new("load_elf_to_flash");
+    let device = EmulatedDevice::new(TargetID::Rp2350);
+    let data = test_data(5000, 6);
+    let path = dir.path("flash.elf");
+    std::fs::write(&path, write_elf(PICO_FLASH_START, &data)).unwrap();
+
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    tool.load(&path).unwrap();
+    assert_eq!(flash_at(&device, PICO_FLASH_START, data.len()), data);
+    assert!(matches!(
+        device.reboot_requested(),
+        Some(RebootRequest::Reboot2 { flags: 0, .. })
+    ));
+}
+
+#[test]
+fn load_elf_to_ram() {
+    let dir = TempDir::new("load_elf_to_ram");
+    let device = EmulatedDevice::new(TargetID::Rp2350);
+    let data = test_data(5000, 7);
+    let path = dir.path("ram.elf");
+    std::fs::write(&path, write_elf(PICO_SRAM_START, &data)).unwrap();
+
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    tool.load(&path).unwrap();
+    assert_eq!(device.sram()[..data.len()], data);
+    assert!(device.flash().iter().all(|&b| b == 0xff));
+    // the RAM image window is rounded out to whole 4KiB pages
+    assert_eq!(
+        device.reboot_requested(),
+        Some(RebootRequest::Reboot2 {
+            flags: 3,
+            delay: 500,
+            p0: PICO_SRAM_START,
+            p1: 2 * PICO_SECTOR_SIZE,
+        })
+    );
+}
+
+#[test]
+fn load_bin_at_offset() {
+    let dir = TempDir::new("load_bin_at_offset");
+    let device = EmulatedDevice::new(TargetID::Rp2350);
+    let existing = test_data(2 * PICO_SECTOR_SIZE as usize, 3);
+    device.load_flash(PICO_FLASH_START, &existing);
+    let data = test_data(1000, 4);
+    let path = dir.path("offset.bin");
+    std::fs::write(&path, &data).unwrap();
+
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    tool.flash_bin(&path, 0x1100).unwrap();
+    assert_eq!(
+        flash_at(&device, PICO_FLASH_START + 0x1100, data.len()),
+        data
+    );
+    // the rest of the sectors it touched are kept
+    assert_eq!(
+        flash_at(&device, PICO_FLASH_START, 0x1100),
+        existing[..0x1100]
+    );
+    let end = 0x1100 + data.len();
+    assert_eq!(
+        flash_at(&device, PICO_FLASH_START + end as u32, existing.len() - end),
+        existing[end..]
+    );
+}
+
+#[test]
+fn load_bin_past_the_end_of_flash() {
+    let dir = TempDir::new("load_bin_past_the_end_of_flash");
+    // inside the 16MiB window, but past the end of the 2MiB fitted
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let path = dir.path("too-long.bin");
+    std::fs::write(&path, test_data(2000, 16)).unwrap();
+
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    let offset = (2 << 20) - 1000;
+    assert!(matches!(
+        tool.flash_bin(&path, offset),
+        Err(Error::OutOfFlash { .. })
+    ));
+    assert!(matches!(
+        tool.verify_bin(&path, offset),
+        Err(Error::OutOfFlash { .. })
+    ));
+    assert!(device.flash().iter().all(|&b| b == 0xff));
+}
+
+#[test]
+fn load_bin_that_looks_like_hex() {
+    let dir = TempDir::new("load_bin_that_looks_like_hex");
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let mut data = test_data(300, 14);
+    data[0] = b':';
+    let path = dir.path("colon.bin");
+    std::fs::write(&path, &data).unwrap();
+
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    tool.load(&path).unwrap();
+    assert_eq!(flash_at(&device, PICO_FLASH_START, data.len()), data);
+}
+
+#[test]
+fn load_hex() {
+    let dir = TempDir::new("load_hex");
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    // crosses a 64KiB boundary, so needs a second extended address record
+    let addr = PICO_FLASH_START + 0xff80;
+    let data = test_data(300, 5);
+    let path = dir.path("load.hex");
+    std::fs::write(&path, write_ihex(addr, &data)).unwrap();
+
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    let summary = tool.load(&path).unwrap();
+    assert_eq!(summary.sectors_written, 2);
+    assert_eq!(flash_at(&device, addr, data.len()), data);
+}
+
+#[test]
+fn differential_load_only_rewrites_changed_sectors() {
+    let dir = TempDir::new("differential_load_only_rewrites_changed_sectors");
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let path = dir.path("diff.bin");
+    let mut data = test_data(4 * PICO_SECTOR_SIZE as usize, 22);
+    std::fs::write(&path, &data).unwrap();
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    tool.flash_bin(&path, 0).unwrap();
+    let device = replug(&device, TargetID::Rp2040, "E6614103E7");
+
+    let changed = PICO_FLASH_START + 2 * PICO_SECTOR_SIZE;
+    let sector = changed..changed + PICO_SECTOR_SIZE;
+    data[2 * PICO_SECTOR_SIZE as usize + 100] ^= 0xff;
+    std::fs::write(&path, &data).unwrap();
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    tool.set_differential(true);
+    let summary = tool.flash_bin(&path, 0).unwrap();
+    assert_eq!(summary.sectors_written, 1);
+    assert_eq!(summary.sectors_skipped, 3);
+    let erased = device.take_erased();
+    assert_eq!(erased.len(), 1);
+    assert_eq!(erased[0], sector);
+    let written = device.take_written();
+    assert!(!written.is_empty());
+    assert!(written
+        .iter()
+        .all(|range| range.start >= sector.start && range.end <= sector.end));
+    assert_eq!(flash_at(&device, PICO_FLASH_START, data.len()), data);
+}
+
+#[test]
+fn sector_cache_notices_sectors_changed_behind_its_back() {
+    let dir = TempDir::new("sector_cache_notices_sectors_changed_behind_its_back");
+    let cache_dir = dir.path("cache");
+    let data = test_data(8 * PICO_SECTOR_SIZE as usize, 15);
+    let path = dir.path("changed-cache.uf2");
+    std::fs::write(&path, write_uf2(PICO_FLASH_START, &data, Uf2Family::Rp2040)).unwrap();
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    device.set_serial_number("E0C912952D54");
+
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    tool.set_cache_dir(Some(cache_dir.clone()));
+    assert_eq!(tool.load(&path).unwrap().sectors_written, 8);
+
+    // written by something that doesn't know about the cache
+    let device = replug(&device, TargetID::Rp2040, "E0C912952D54");
+    device.load_flash(PICO_FLASH_START + 0x2000, &[0xff; 0x2000]);
+
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    tool.set_cache_dir(Some(cache_dir));
+    let summary = tool.load(&path).unwrap();
+    assert_eq!(summary.sectors_written, 2);
+    assert_eq!(flash_at(&device, PICO_FLASH_START, data.len()), data);
+}
+
+#[test]
+fn rebooting_device_rejects_commands() {
+    let dir = TempDir::new("rebooting_device_rejects_commands");
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let path = dir.path("rebooting.bin");
+    std::fs::write(&path, test_data(256, 8)).unwrap();
+
+    let mut tool = PicoTool::with_transport(device).unwrap();
+    tool.load(&path).unwrap();
+    assert_eq!(status(tool.verify(&path)), PicobootStatus::Rebooting);
+}
+
+#[test]
+fn save_and_verify_round_trip() {
+    let dir = TempDir::new("save_and_verify_round_trip");
+    for (target, name) in [
+        (TargetID::Rp2040, "round-trip.bin"),
+        (TargetID::Rp2040, "round-trip.uf2"),
+        (TargetID::Rp2350, "round-trip.uf2"),
+        (TargetID::Rp2350, "round-trip.elf"),
+    ] {
+        let device = EmulatedDevice::new(target);
+        let data = test_data(10_000, 12);
+        device.load_flash(PICO_FLASH_START, &data);
+        let path = dir.path(&format!("{target:?}-{name}"));
+
+        let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+        let range = SaveRange::Range {
+            start: PICO_FLASH_START,
+            end: PICO_FLASH_START + data.len() as u32,
+        };
+        tool.save(range, SaveFormat::for_path(&path), &path)
+            .unwrap();
+        assert!(tool.verify(&path).unwrap().matches(), "{target:?} {name}");
+
+        // and a change on the device shows up
+        device.load_flash(PICO_FLASH_START + 5000, &[!data[5000]]);
+        let report = tool.verify(&path).unwrap();
+        assert_eq!(report.bytes_different, 1, "{target:?} {name}");
+        assert_eq!(report.mismatches[0].addr, PICO_FLASH_START + 5000);
+    }
+}
+
+#[test]
+fn save_program_stops_after_last_used_sector() {
+    let dir = TempDir::new("save_program_stops_after_last_used_sector");
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let data = test_data(PICO_SECTOR_SIZE as usize + 10, 13);
+    device.load_flash(PICO_FLASH_START, &data);
+    let path = dir.path("program.bin");
+
+    let mut tool = PicoTool::with_transport(device).unwrap();
+    let saved = tool
+        .save(SaveRange::Program, SaveFormat::Bin, &path)
+        .unwrap();
+    assert_eq!(
+        saved,
+        PICO_FLASH_START..PICO_FLASH_START + 2 * PICO_SECTOR_SIZE
+    );
+    assert_eq!(std::fs::read(&path).unwrap()[..data.len()], data);
+}
+
+#[test]
+fn erase_range_with_blank_check() {
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let data = test_data(4 * PICO_SECTOR_SIZE as usize, 9);
+    device.load_flash(PICO_FLASH_START, &data);
+
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    let start = PICO_FLASH_START + PICO_SECTOR_SIZE;
+    let end = start + 2 * PICO_SECTOR_SIZE;
+    assert_eq!(
+        tool.erase(EraseRange::Range { start, end }, true).unwrap(),
+        start..end
+    );
+    let flash = device.flash();
+    let sector = PICO_SECTOR_SIZE as usize;
+    assert_eq!(flash[..sector], data[..sector]);
+    assert!(flash[sector..3 * sector].iter().all(|&b| b == 0xff));
+    assert_eq!(flash[3 * sector..4 * sector], data[3 * sector..]);
+}
+
+#[test]
+fn erase_rejects_unaligned_range() {
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let mut tool = PicoTool::with_transport(device).unwrap();
+    let range = EraseRange::Range {
+        start: PICO_FLASH_START + 0x100,
+        end: PICO_FLASH_START + PICO_SECTOR_SIZE,
+    };
+    assert!(matches!(
+        tool.erase(range, false),
+        Err(Error::SectorAlignment { .. })
+    ));
+}
+
+#[test]
+fn erase_throws_away_the_sector_cache() {
+    let dir = TempDir::new("erase_throws_away_the_sector_cache");
+    let cache_dir = dir.path("erase-cache");
+    let data = test_data(3 * PICO_SECTOR_SIZE as usize, 14);
+    let path = dir.path("erase-cache.bin");
+    std::fs::write(&path, &data).unwrap();
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    device.set_serial_number("E0C912952D54");
+
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    tool.set_cache_dir(Some(cache_dir.clone()));
+    assert_eq!(tool.load(&path).unwrap().sectors_written, 3);
+
+    let device = replug(&device, TargetID::Rp2040, "E0C912952D54");
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    tool.set_cache_dir(Some(cache_dir.clone()));
+    let middle = PICO_FLASH_START + PICO_SECTOR_SIZE;
+    let range = EraseRange::Range {
+        start: middle,
+        end: middle + PICO_SECTOR_SIZE,
+    };
+    tool.erase(range, false).unwrap();
+
+    // the first and last sectors still match, but the cache can't be trusted
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    tool.set_cache_dir(Some(cache_dir));
+    tool.load(&path).unwrap();
+    assert_eq!(flash_at(&device, PICO_FLASH_START, data.len()), data);
+}
+
+#[test]
+fn erase_all() {
+    for (target, size) in [(TargetID::Rp2040, 2 << 20), (TargetID::Rp2350, 8 << 20)] {
+        let device = EmulatedDevice::with_flash_size(target, size);
+        device.load_flash(PICO_FLASH_START, &test_data(64 * 1024, 10));
+        let last = PICO_FLASH_START + size as u32 - PICO_SECTOR_SIZE;
+        device.load_flash(last, &test_data(16, 11));
+
+        let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+        let erased = tool.erase(EraseRange::All, true).unwrap();
+        assert_eq!(erased.len(), size, "{target:?}");
+        assert!(device.flash().iter().all(|&b| b == 0xff), "{target:?}");
+    }
+}
+
+#[test]
+fn erase_all_takes_the_size_from_the_device() {
+    for target in [TargetID::Rp2040, TargetID::Rp2350] {
+        // blank, and with the same data at the start of each half, which a size
+        // worked out from the contents would get wrong
+        let blank = EmulatedDevice::new(target);
+        let halves = EmulatedDevice::with_flash_size(target, 1 << 20);
+        halves.load_flash(PICO_FLASH_START, &test_data(4096, 13));
+        halves.load_flash(PICO_FLASH_START + (1 << 19), &test_data(4096, 13));
+        for device in [blank, halves] {
+            let size = device.flash().len();
+            let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+            let erased = tool.erase(EraseRange::All, false).unwrap();
+            assert_eq!(erased.len(), size, "{target:?}");
+            assert!(device.flash().iter().all(|&b| b == 0xff), "{target:?}");
+        }
+
+        // no real part has this size, so the device can't say
+        let device = EmulatedDevice::with_flash_size(target, 3 << 20);
+        let mut tool = PicoTool::with_transport(device).unwrap();
+        assert!(
+            matches!(
+                tool.erase(EraseRange::All, false),
+                Err(Error::UnknownFlashSize)
+            ),
+            "{target:?}"
+        );
+    }
+}
+
+#[test]
+fn reboot_rp2040_to_application() {
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    tool.reboot(
+        RebootMode::PcSp {
+            pc: 0x2000_0101,
+            sp: 0x2000_4000,
+        },
+        10,
+        false,
+    )
+    .unwrap();
+    assert_eq!(
+        device.reboot_requested(),
+        Some(RebootRequest::Reboot {
+            pc: 0x2000_0101,
+            sp: 0x2000_4000,
+            delay: 10
+        })
+    );
+}
+
+#[test]
+fn reboot_rp2040_to_bootsel_runs_code_from_sram() {
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    let mode = RebootMode::Bootsel {
+        disable_msd: true,
+        disable_picoboot: false,
+        activity_led: Some(ActivityLed {
+            pin: 25,
+            active_low: false,
+        }),
+    };
+    tool.reboot(mode, 0, false).unwrap();
+    let sram = device.sram();
+    // the literal pool after the code holds the gpio and disable masks
+    assert_eq!(sram[..2], [0x14, 0x20]);
+    assert_eq!(sram[28..32], (1u32 << 25).to_le_bytes());
+    assert_eq!(sram[32..36], 1u32.to_le_bytes());
+}
+
+#[test]
+fn reboot_rp2040_refuses_rp2350_modes() {
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    let mode = RebootMode::RamImage {
+        start: PICO_SRAM_START,
+        size: PICO_SECTOR_SIZE,
+    };
+    assert!(matches!(
+        tool.reboot(mode, 0, false),
+        Err(Error::NotSupported { .. })
+    ));
+    assert_eq!(device.reboot_requested(), None);
+}
+
+#[test]
+fn reboot_rp2040_refuses_an_led_it_doesnt_have() {
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    for pin in [30, 40] {
+        let mode = RebootMode::Bootsel {
+            disable_msd: false,
+            disable_picoboot: false,
+            activity_led: Some(ActivityLed {
+                pin,
+                active_low: false,
+            }),
+        };
+        assert!(matches!(
+            tool.reboot(mode, 0, false),
+            Err(Error::InvalidReboot(_))
+        ));
+    }
+    assert_eq!(device.sram()[..2], [0, 0]);
+}
+
+#[test]
+fn reboot_rp2350_to_bootsel() {
+    let device = EmulatedDevice::new(TargetID::Rp2350);
+    let mut tool = PicoTool::with_transport(device.clone()).unwrap();
+    let mode = RebootMode::Bootsel {
+        disable_msd: true,
+        disable_picoboot: false,
+        activity_led: Some(ActivityLed {
+            pin: 25,
+            active_low: true,
+        }),
+    };
+    tool.reboot(mode, 100, false).unwrap();
+    assert_eq!(
+        device.reboot_requested(),
+        Some(RebootRequest::Reboot2 {
+            flags: 2,
+            delay: 100,
+            p0: 0x1 | 0x10 | 0x20,
+            p1: 25,
+        })
+    );
+}
+
+#[test]
+fn command_dropped_part_way_through() {
+    let device = EmulatedDevice::new(TargetID::Rp2040);
+    let data = test_data(256, 17);
+    device.load_flash(PICO_FLASH_START, &data);
+    device.set_timing(Timing {
+        transfer: Duration::from_millis(50),
+        ..Timing::typical()
+    });
+
+    let mut conn = AsyncPicobootConnection::with_transport(device.clone());
+    block_on(async {
+        // given up on between the data and the ack
+        let read = conn.flash_read(PICO_FLASH_START, 256);
+        let dropped = read
+            .or(async {
+                Timer::after(Duration::from_millis(75)).await;
+                Err(Error::Timeout)
+            })
+            .await;
+        assert!(matches!(dropped, Err(Error::Timeout)));
+
+        assert_eq!(conn.flash_read(PICO_FLASH_START, 256).await.unwrap(), data);
+    });
+}